@@ -0,0 +1,64 @@
+use std::net::SocketAddr;
+
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Deserialize;
+use tracing::error;
+
+use crate::AppState;
+
+const DNS_MESSAGE: &str = "application/dns-message";
+
+// ================= DNS over HTTPS (RFC 8484) =================
+// Même traitement que sur le port 53 ; le TLS est laissé au reverse proxy
+#[derive(Deserialize)]
+pub(crate) struct DohParams {
+    dns: String,
+}
+
+// GET /dns-query?dns=<requête en base64url, sans padding>
+pub(crate) async fn dns_query_get(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(params): Query<DohParams>,
+) -> Response {
+    // Padding toléré même si la RFC l'exclut, certains clients l'ajoutent
+    let Ok(query) = URL_SAFE_NO_PAD.decode(params.dns.trim_end_matches('=')) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    answer(&state, peer, &query).await
+}
+
+// POST /dns-query, requête brute dans le corps
+pub(crate) async fn dns_query_post(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let is_dns_message = headers
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|ct| ct.as_bytes().eq_ignore_ascii_case(DNS_MESSAGE.as_bytes()));
+    if !is_dns_message {
+        return StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response();
+    }
+    answer(&state, peer, &body).await
+}
+
+async fn answer(state: &AppState, peer: SocketAddr, query: &[u8]) -> Response {
+    match state.dns.handle_query(query, peer.ip()).await {
+        Ok(Some(resp)) => ([(header::CONTENT_TYPE, DNS_MESSAGE)], resp).into_response(),
+        // Message DNS illisible
+        Ok(None) => StatusCode::BAD_REQUEST.into_response(),
+        Err(e) => {
+            error!(client = %peer.ip(), error = format!("{:#}", e), "Erreur DoH");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
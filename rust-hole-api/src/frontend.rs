@@ -0,0 +1,31 @@
+use std::borrow::Cow;
+
+use axum::{
+    body::Body,
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+
+// ================= Dashboard on the API port =================
+// Mode unifié : les fichiers du dashboard, embarqués par le binaire, sont
+// servis par le serveur de l'API pour tout ce qui n'est pas sous /api
+pub struct StaticFile {
+    pub status: u16,
+    pub content_type: String,
+    pub cache_control: Option<&'static str>,
+    pub body: Cow<'static, [u8]>,
+}
+
+// Chemin sans le `/` initial
+pub type FrontendHandler = fn(&str) -> StaticFile;
+
+pub(crate) async fn serve(frontend: FrontendHandler, uri: Uri) -> Response {
+    let file = frontend(uri.path().trim_start_matches('/'));
+    let status = StatusCode::from_u16(file.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let mut resp = (status, [(header::CONTENT_TYPE, file.content_type)], Body::from(file.body)).into_response();
+    if let Some(cache_control) = file.cache_control {
+        resp.headers_mut()
+            .insert(header::CACHE_CONTROL, header::HeaderValue::from_static(cache_control));
+    }
+    resp
+}
@@ -0,0 +1,64 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use rust_hole_db::normalize_blocked_domain;
+
+// Noms présents dans tout fichier hosts, jamais à bloquer
+const IGNORED_HOSTS: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-mcastprefix",
+    "ip6-allnodes",
+    "ip6-allrouters",
+    "ip6-allhosts",
+    "0.0.0.0",
+];
+
+pub struct ParsedHosts {
+    pub domains: Vec<String>,
+    // Noms illisibles ou en double dans le fichier
+    pub skipped: usize,
+}
+
+// Accepte le format hosts (`0.0.0.0 ads.example.com`) comme une simple liste
+// de domaines, un ou plusieurs par ligne, avec des commentaires `#`
+pub fn parse_hosts(content: &str) -> ParsedHosts {
+    let mut seen = HashSet::new();
+    let mut domains = Vec::new();
+    let mut skipped = 0;
+
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut tokens = line.split_whitespace().peekable();
+        if tokens.peek().is_some_and(|t| t.parse::<IpAddr>().is_ok()) {
+            tokens.next();
+        }
+
+        // Une IP seule sur sa ligne est illisible
+        let mut has_name = false;
+        for token in tokens {
+            has_name = true;
+            if IGNORED_HOSTS.contains(&token.trim_end_matches('.').to_ascii_lowercase().as_str()) {
+                continue;
+            }
+            match normalize_blocked_domain(token) {
+                Ok(domain) if seen.insert(domain.clone()) => domains.push(domain),
+                _ => skipped += 1,
+            }
+        }
+        if !has_name {
+            skipped += 1;
+        }
+    }
+
+    ParsedHosts { domains, skipped }
+}
@@ -1,45 +1,1576 @@
+mod doh;
+pub mod frontend;
+mod hosts;
+pub mod sources;
+
+use async_trait::async_trait;
 use axum::{
-    routing::{get},
-    Json, Router,
+    body::Body,
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    extract::{DefaultBodyLimit, Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, patch, post, put},
+    BoxError, Json, Router,
 };
+use futures_util::future::join_all;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::TcpListener;
-use rust_hole_db::get_all_blocked_domains;
+use tokio::sync::broadcast;
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::CorsLayer;
+use tracing::{error, info, warn};
+use rust_hole_db::{
+    clear_query_log, delete_blocklist_source, delete_local_record, delete_regex_rule,
+    get_all_blocked_domains, get_all_local_records, get_all_regex_rules, get_blocked_domains_page,
+    get_blocklist_categories, get_blocklist_sources_info, get_query_log, get_query_timeseries,
+    get_top_blocked, get_top_clients, insert_blocked_domains, insert_blocklist_source,
+    insert_local_record, insert_regex_rule, normalize_blocked_domain, normalize_domain, ping_db,
+    search_blocked_domains, set_blocklist_category_enabled, set_blocklist_source,
+    stream_blocked_domains, update_local_record, BlocklistSourceInfo, CategoryInfo, ClientCount,
+    DomainCount, NewLocalRecord, QueryLogFilter, QuerySource, QueryStatus, TimeBucket,
+};
 use rust_hole_db::models::blocked_domains::Model as BlockedDomainModel;
+use rust_hole_db::models::blocklist_sources::Model as BlocklistSourceModel;
+use rust_hole_db::models::local_records::Model as LocalRecordModel;
+use rust_hole_db::models::regex_blocklist::Model as RegexRuleModel;
+use rust_hole_db::models::query_log::Model as QueryLogModel;
+
+const DEFAULT_QUERYLOG_LIMIT: u64 = 50;
+const MAX_QUERYLOG_LIMIT: u64 = 500;
+const DEFAULT_BLOCKLIST_LIMIT: u64 = 100;
+const MAX_BLOCKLIST_LIMIT: u64 = 1000;
+const DEFAULT_SEARCH_LIMIT: u64 = 50;
+const MAX_SEARCH_LIMIT: u64 = 500;
+const DEFAULT_TOP_LIMIT: u64 = 10;
+const MAX_TOP_LIMIT: u64 = 100;
+const DEFAULT_TIMESERIES_WINDOW: u64 = 24;
+const MAX_TIMESERIES_WINDOW: u64 = 1000;
+// Les listes hosts publiques dépassent largement la limite par défaut de 2 Mo
+const MAX_IMPORT_SIZE: usize = 32 * 1024 * 1024;
+const DEFAULT_LOCAL_TTL: u32 = 300;
+// Secondes : de la réponse d'un résolveur local à un upstream qui expire
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0];
+
+// Actions exposées par le serveur DNS à l'API
+#[async_trait]
+pub trait DnsHandle: Send + Sync {
+    async fn reload_blocklist(&self) -> anyhow::Result<usize>;
+    // Ajout manuel enregistré en base et appliqué sans recharger la blocklist.
+    // `None` si le domaine a déjà été ajouté à la main
+    async fn add_domain_live(&self, domain: &str) -> anyhow::Result<Option<BlockedDomainModel>>;
+    // Pendant de add_domain_live : `false` si aucune ligne ne correspond
+    async fn remove_domain_live(&self, id: u32) -> anyhow::Result<bool>;
+    fn stats(&self) -> StatsSnapshot;
+    // Suspend le blocage pendant `duration`, puis il reprend de lui-même
+    fn disable_blocking(&self, duration: Duration);
+    fn enable_blocking(&self);
+    // Requêtes à venir, pour le journal en direct
+    fn subscribe_queries(&self) -> broadcast::Receiver<QueryEvent>;
+    // Nom PTR d'un client, mis en cache côté DNS
+    async fn reverse_lookup(&self, ip: IpAddr) -> Option<String>;
+    // Requête au format wire (DoH), même traitement que sur le port 53.
+    // `None` si le message est illisible
+    async fn handle_query(&self, query: &[u8], client: IpAddr) -> anyhow::Result<Option<Vec<u8>>>;
+    // Contenu du cache, du plus au moins récemment utilisé
+    fn cache_snapshot(&self) -> Vec<CacheEntryInfo>;
+    // Vide le cache, ou seulement les entrées d'un domaine (tous types confondus).
+    // Retourne le nombre d'entrées retirées
+    fn flush_cache(&self, domain: Option<&str>) -> usize;
+    // Même décision que pour une requête DNS de `client`
+    fn check_domain(&self, client: IpAddr, domain: &str) -> DomainCheck;
+}
+
+#[derive(Serialize)]
+pub struct DomainCheck {
+    // Faux si le blocage est suspendu, même quand une règle correspond
+    pub blocked: bool,
+    // Entrée de la blocklist, de la blocklist d'un groupe ou motif qui correspond
+    #[serde(rename = "matchedRule")]
+    pub matched_rule: Option<String>,
+    // Source de la règle : nom de la liste, "manual", "group N" ou "regex"
+    pub source: Option<String>,
+    pub allowlisted: bool,
+}
 
 #[derive(Serialize)]
-struct HelloResponse {
-    message: String,
+pub struct CacheEntryInfo {
+    pub domain: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    // Sous-réseau ECS de la requête, quand il fait partie de la clé
+    pub subnet: Option<String>,
+    pub rcode: String,
+    pub negative: bool,
+    // "A 93.184.216.34", "CNAME example.net."
+    pub answers: Vec<String>,
+    pub expires_in: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct QueryEvent {
+    pub timestamp: i64,
+    pub client: String,
+    pub domain: String,
+    #[serde(rename = "type")]
+    pub query_type: String,
+    pub outcome: String,
+    pub source: String,
+}
+
+#[derive(Serialize)]
+pub struct StatsSnapshot {
+    pub total_queries: u64,
+    pub blocked_queries: u64,
+    pub cache_hits: u64,
+    pub negative_cache_hits: u64,
+    pub forwarded_queries: u64,
+    pub local_queries: u64,
+    pub servfail_queries: u64,
+    // Clients hors ACL et rate limit, dont `rate_limited_queries`
+    pub refused_queries: u64,
+    pub formerr_queries: u64,
+    pub rate_limited_queries: u64,
+    pub dnssec_failures: u64,
+    // Anti-stampede : requêtes transmises à l'upstream, et requêtes identiques
+    // servies par leur réponse sans nouvel appel
+    pub leader_queries: u64,
+    pub coalesced_queries: u64,
+    pub percent_blocked: f64,
+    // Nombre de requêtes par type (A, AAAA, HTTPS…), plus "other"
+    pub query_types: BTreeMap<String, u64>,
+    pub upstream_latency: LatencySnapshot,
+    pub cache_latency: LatencySnapshot,
+    pub blocklist: BlocklistInfo,
+    pub blocking_enabled: bool,
+    // Secondes restantes avant la reprise du blocage
+    pub blocking_disabled_secs: Option<u64>,
+}
+
+// Blocklist actuellement chargée en mémoire par le serveur DNS
+#[derive(Default, Serialize)]
+pub struct BlocklistInfo {
+    pub domains: usize,
+    // Timestamp Unix du dernier rechargement réussi
+    pub last_reload: Option<i64>,
+}
+
+#[derive(Default, Serialize)]
+pub struct LatencySnapshot {
+    pub count: u64,
+    pub min_ms: f64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
 }
 
+#[derive(Clone)]
+struct AppState {
+    dns: Arc<dyn DnsHandle>,
+    metrics: PrometheusHandle,
+    build: BuildInfo,
+    started: Instant,
+    http: sources::HttpClient,
+}
 
 #[derive(Deserialize)]
 struct CreateBlockedDomain {
     domain: String,
 }
 
+#[derive(Deserialize)]
+struct QueryLogParams {
+    limit: Option<u64>,
+    offset: Option<u64>,
+    domain: Option<String>,
+    status: Option<QueryStatus>,
+    source: Option<QuerySource>,
+}
+
+#[derive(Deserialize)]
+struct BlocklistParams {
+    limit: Option<u64>,
+    offset: Option<u64>,
+    // Toute la table d'un coup, comme avant la pagination (petites installations)
+    #[serde(default)]
+    all: bool,
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    q: String,
+    limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct TopParams {
+    limit: Option<u64>,
+}
+
+impl TopParams {
+    fn limit(&self) -> u64 {
+        self.limit.unwrap_or(DEFAULT_TOP_LIMIT).min(MAX_TOP_LIMIT)
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TimeInterval {
+    Minute,
+    #[default]
+    Hour,
+    Day,
+}
+
+impl TimeInterval {
+    fn duration(self) -> Duration {
+        match self {
+            TimeInterval::Minute => Duration::from_secs(60),
+            TimeInterval::Hour => Duration::from_secs(3600),
+            TimeInterval::Day => Duration::from_secs(86400),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TimeseriesParams {
+    #[serde(default)]
+    interval: TimeInterval,
+    // Nombre de tranches, la tranche en cours comprise
+    window: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct QueryLogPage {
+    total: u64,
+    limit: u64,
+    offset: u64,
+    entries: Vec<QueryLogModel>,
+}
+
+#[derive(Serialize)]
+struct BlocklistPage {
+    total: u64,
+    limit: u64,
+    offset: u64,
+    entries: Vec<BlockedDomainModel>,
+}
+
+#[derive(Deserialize)]
+struct ClearQueryLogParams {
+    // Timestamp Unix : seules les entrées plus anciennes sont supprimées
+    before: Option<i64>,
+}
+
 #[derive(Serialize)]
-struct BlockedDomain {
-    id: u32,
+struct DeletedCount {
+    deleted: u64,
+}
+
+#[derive(Deserialize)]
+struct CheckParams {
+    domain: String,
+    // Les blocklists de groupe dépendent du client, aucune si absent
+    client: Option<IpAddr>,
+}
+
+#[derive(Deserialize)]
+struct FlushCacheParams {
+    domain: Option<String>,
+}
+
+#[derive(Serialize)]
+struct EvictedCount {
+    evicted: usize,
+}
+
+#[derive(Deserialize)]
+struct CreateRegexRule {
+    pattern: String,
+}
+
+#[derive(Deserialize)]
+struct LocalRecordPayload {
     domain: String,
+    record_type: String,
+    value: String,
+    ttl: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct DisableBlocking {
+    seconds: u64,
+}
+
+#[derive(Serialize)]
+struct ImportResult {
+    added: usize,
+    skipped: usize,
+}
+
+// Vérifie que la valeur correspond au type d'enregistrement (A, AAAA ou CNAME)
+fn parse_local_record(payload: LocalRecordPayload) -> Option<NewLocalRecord> {
+    let domain = normalize_domain(&payload.domain).ok()?;
+    let record_type = payload.record_type.trim().to_ascii_uppercase();
+    let value = payload.value.trim();
+
+    let value = match record_type.as_str() {
+        "A" => value.parse::<Ipv4Addr>().ok()?.to_string(),
+        "AAAA" => value.parse::<Ipv6Addr>().ok()?.to_string(),
+        "CNAME" => normalize_domain(value).ok()?,
+        _ => return None,
+    };
+
+    Some(NewLocalRecord {
+        domain,
+        record_type,
+        value,
+        ttl: i64::from(payload.ttl.unwrap_or(DEFAULT_LOCAL_TTL)),
+    })
+}
+
+async fn get_blocked_domains(
+    Query(params): Query<BlocklistParams>,
+) -> Result<Response, StatusCode> {
+    if params.all {
+        return match get_all_blocked_domains().await {
+            Ok(domains) => Ok(Json(domains).into_response()),
+            Err(e) => {
+                error!(error = %e, "Erreur lors de la lecture de la blocklist");
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        };
+    }
+
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_BLOCKLIST_LIMIT)
+        .min(MAX_BLOCKLIST_LIMIT);
+    let offset = params.offset.unwrap_or(0);
+    match get_blocked_domains_page(limit, offset).await {
+        Ok((entries, total)) => Ok(Json(BlocklistPage { total, limit, offset, entries }).into_response()),
+        Err(e) => {
+            error!(error = %e, "Erreur lors de la lecture de la blocklist");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn search_blocklist(
+    Query(params): Query<SearchParams>,
+) -> Result<Json<Vec<BlockedDomainModel>>, StatusCode> {
+    if params.q.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).min(MAX_SEARCH_LIMIT);
+
+    search_blocked_domains(&params.q, limit).await.map(Json).map_err(|e| {
+        error!(error = %e, "Erreur lors de la recherche dans la blocklist");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+async fn reload_dns_blocklist(state: &AppState) {
+    if let Err(e) = state.dns.reload_blocklist().await {
+        error!("Erreur lors du rechargement de la blocklist: {:#}", e);
+    }
+}
+
+async fn create_blocked_domain(
+    State(state): State<AppState>,
+    Json(payload): Json<CreateBlockedDomain>,
+) -> Result<(StatusCode, Json<BlockedDomainModel>), StatusCode> {
+    let domain = normalize_blocked_domain(&payload.domain).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let domain = domain.as_str();
+
+    match state.dns.add_domain_live(domain).await {
+        Ok(Some(model)) => Ok((StatusCode::CREATED, Json(model))),
+        Ok(None) => Err(StatusCode::CONFLICT),
+        Err(e) => {
+            error!(domain, error = format!("{:#}", e), "Erreur lors de l'insertion");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn import_blocked_domains(
+    State(state): State<AppState>,
+    body: String,
+) -> Result<Json<ImportResult>, StatusCode> {
+    let parsed = hosts::parse_hosts(&body);
+    let total = parsed.domains.len();
+
+    match insert_blocked_domains(parsed.domains).await {
+        Ok(added) => {
+            if added > 0 {
+                reload_dns_blocklist(&state).await;
+            }
+            // Déjà ajoutés à la main, en double dans le fichier ou illisibles
+            let skipped = total - added + parsed.skipped;
+            info!(added, skipped, "Import de la blocklist");
+            Ok(Json(ImportResult { added, skipped }))
+        }
+        Err(e) => {
+            error!(error = %e, "Erreur lors de l'import");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Format hosts par défaut, JSON si le client le demande
+async fn export_blocked_domains(headers: HeaderMap) -> Response {
+    let wants_json = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("application/json"));
+
+    let domains = stream_blocked_domains()
+        .inspect_err(|e| error!(error = %e, "Erreur lors de l'export"))
+        .map_err(BoxError::from);
+
+    if wants_json {
+        // Tableau JSON écrit élément par élément
+        let entries = domains.enumerate().map(|(i, domain)| {
+            let mut chunk = if i == 0 { Vec::new() } else { b",".to_vec() };
+            serde_json::to_writer(&mut chunk, &domain?)?;
+            Ok::<_, BoxError>(chunk)
+        });
+        let body = stream::once(async { Ok(b"[".to_vec()) })
+            .chain(entries)
+            .chain(stream::once(async { Ok(b"]".to_vec()) }));
+        ([(header::CONTENT_TYPE, "application/json")], Body::from_stream(body)).into_response()
+    } else {
+        let lines = domains.map_ok(|d| format!("0.0.0.0 {}\n", d.domain));
+        ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], Body::from_stream(lines))
+            .into_response()
+    }
+}
+
+async fn remove_blocked_domain(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+) -> StatusCode {
+    match state.dns.remove_domain_live(id).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!(id, error = format!("{:#}", e), "Erreur lors de la suppression");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
 }
 
-async fn get_blocked_domains() -> Json<Vec<BlockedDomainModel>> {
-    let blocked_domains = get_all_blocked_domains().await.unwrap();
-        Json(blocked_domains)
+async fn get_regex_rules() -> Result<Json<Vec<RegexRuleModel>>, StatusCode> {
+    get_all_regex_rules().await.map(Json).map_err(|e| {
+        error!(error = %e, "Erreur lors de la lecture des motifs");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
 }
 
+// Un motif invalide est refusé avec le message du compilateur de regex
+async fn create_regex_rule(
+    State(state): State<AppState>,
+    Json(payload): Json<CreateRegexRule>,
+) -> Result<(StatusCode, Json<RegexRuleModel>), (StatusCode, String)> {
+    let pattern = payload.pattern.trim();
+    if let Err(e) = regex::Regex::new(pattern) {
+        return Err((StatusCode::BAD_REQUEST, format!("Motif invalide: {}", e)));
+    }
+
+    match insert_regex_rule(pattern).await {
+        Ok(model) => {
+            reload_dns_blocklist(&state).await;
+            Ok((StatusCode::CREATED, Json(model)))
+        }
+        Err(e) => {
+            error!(pattern, error = %e, "Erreur lors de l'insertion du motif");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()))
+        }
+    }
+}
+
+async fn remove_regex_rule(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+) -> StatusCode {
+    match delete_regex_rule(id).await {
+        Ok(true) => {
+            reload_dns_blocklist(&state).await;
+            StatusCode::NO_CONTENT
+        }
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!(id, error = %e, "Erreur lors de la suppression du motif");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateSource {
+    url: String,
+    category: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CreatedSource {
+    #[serde(flatten)]
+    source: BlocklistSourceInfo,
+    // Échec du premier téléchargement : la source est gardée et retentée à
+    // la prochaine mise à jour périodique
+    error: Option<String>,
+}
+
+async fn get_blocklist_sources() -> Result<Json<Vec<BlocklistSourceInfo>>, StatusCode> {
+    get_blocklist_sources_info().await.map(Json).map_err(|e| {
+        error!(error = %e, "Erreur lors de la lecture des sources");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+// La liste est téléchargée tout de suite : la réponse donne son nombre de domaines
+async fn create_blocklist_source(
+    State(state): State<AppState>,
+    Json(payload): Json<CreateSource>,
+) -> Result<(StatusCode, Json<CreatedSource>), (StatusCode, String)> {
+    let url = payload.url.trim();
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+        _ => return Err((StatusCode::BAD_REQUEST, format!("URL invalide: {}", url))),
+    }
+    let category = payload.category.as_deref().filter(|c| !c.trim().is_empty());
+
+    let model = match insert_blocklist_source(url, category).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return Err((StatusCode::CONFLICT, String::new())),
+        Err(e) => {
+            error!(url, error = %e, "Erreur lors de l'ajout de la source");
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+        }
+    };
+
+    let error = match sources::refresh_source(&state.http, &model).await {
+        Ok(_) => {
+            reload_dns_blocklist(&state).await;
+            None
+        }
+        Err(e) => {
+            warn!(url, error = format!("{:#}", e), "Échec du premier téléchargement de la source");
+            Some(format!("{:#}", e))
+        }
+    };
+    let source = get_blocklist_sources_info()
+        .await
+        .map_err(|e| {
+            error!(url, error = %e, "Erreur lors de la lecture des sources");
+            (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+        })?
+        .into_iter()
+        .find(|s| s.id == model.id)
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+    Ok((StatusCode::CREATED, Json(CreatedSource { source, error })))
+}
+
+// Retire la source et ses domaines ; `deleted` compte les domaines retirés
+async fn remove_blocklist_source(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+) -> Result<Json<DeletedCount>, StatusCode> {
+    match delete_blocklist_source(id).await {
+        Ok(Some(deleted)) => {
+            reload_dns_blocklist(&state).await;
+            Ok(Json(DeletedCount { deleted }))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!(id, error = %e, "Erreur lors de la suppression de la source");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct UpdateSource {
+    enabled: Option<bool>,
+    // Chaîne vide pour retirer la source de sa catégorie
+    category: Option<String>,
+}
+
+// Désactiver une source retire ses domaines du blocage sans les supprimer
+async fn update_blocklist_source(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+    Json(payload): Json<UpdateSource>,
+) -> Result<Json<BlocklistSourceModel>, StatusCode> {
+    let category = payload
+        .category
+        .map(|c| Some(c).filter(|c| !c.trim().is_empty()));
+    match set_blocklist_source(id, payload.enabled, category).await {
+        Ok(Some(model)) => {
+            reload_dns_blocklist(&state).await;
+            Ok(Json(model))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!(id, error = %e, "Erreur lors de la mise à jour de la source");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn get_categories() -> Result<Json<Vec<CategoryInfo>>, StatusCode> {
+    get_blocklist_categories().await.map(Json).map_err(|e| {
+        error!(error = %e, "Erreur lors de la lecture des catégories");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[derive(Deserialize)]
+struct UpdateCategory {
+    enabled: bool,
+}
+
+// Une catégorie désactivée retire du blocage les domaines de toutes ses sources
+async fn update_category(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(payload): Json<UpdateCategory>,
+) -> Result<Json<CategoryInfo>, StatusCode> {
+    if name.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    match set_blocklist_category_enabled(&name, payload.enabled).await {
+        Ok(category) => {
+            reload_dns_blocklist(&state).await;
+            Ok(Json(category))
+        }
+        Err(e) => {
+            error!(category = %name, error = %e, "Erreur lors de la mise à jour de la catégorie");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn get_local_records() -> Result<Json<Vec<LocalRecordModel>>, StatusCode> {
+    get_all_local_records().await.map(Json).map_err(|e| {
+        error!(error = %e, "Erreur lors de la lecture des enregistrements locaux");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
 
-pub async fn run_api() -> anyhow::Result<()> {
-    let app = Router::new()
-        .route("/blocklist", get(get_blocked_domains));
+async fn create_local_record(
+    State(state): State<AppState>,
+    Json(payload): Json<LocalRecordPayload>,
+) -> Result<(StatusCode, Json<LocalRecordModel>), StatusCode> {
+    let record = parse_local_record(payload).ok_or(StatusCode::BAD_REQUEST)?;
+
+    match insert_local_record(record).await {
+        Ok(model) => {
+            reload_dns_blocklist(&state).await;
+            Ok((StatusCode::CREATED, Json(model)))
+        }
+        Err(e) => {
+            error!(error = %e, "Erreur lors de l'insertion de l'enregistrement local");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn replace_local_record(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+    Json(payload): Json<LocalRecordPayload>,
+) -> Result<Json<LocalRecordModel>, StatusCode> {
+    let record = parse_local_record(payload).ok_or(StatusCode::BAD_REQUEST)?;
+
+    match update_local_record(id, record).await {
+        Ok(Some(model)) => {
+            reload_dns_blocklist(&state).await;
+            Ok(Json(model))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!(id, error = %e, "Erreur lors de la mise à jour de l'enregistrement local");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn remove_local_record(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+) -> StatusCode {
+    match delete_local_record(id).await {
+        Ok(true) => {
+            reload_dns_blocklist(&state).await;
+            StatusCode::NO_CONTENT
+        }
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!(id, error = %e, "Erreur lors de la suppression de l'enregistrement local");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    commit: Option<&'static str>,
+    built_at: u64,
+    uptime_secs: u64,
+}
+
+async fn get_version(State(state): State<AppState>) -> Json<VersionInfo> {
+    Json(VersionInfo {
+        version: state.build.version,
+        commit: state.build.commit,
+        built_at: state.build.built_at,
+        uptime_secs: state.started.elapsed().as_secs(),
+    })
+}
+
+#[derive(Serialize)]
+struct HealthStatus {
+    status: &'static str,
+    db: bool,
+}
+
+// Sonde liveness/readiness : 503 tant que la base ne répond pas
+async fn health() -> (StatusCode, Json<HealthStatus>) {
+    match ping_db().await {
+        Ok(()) => (StatusCode::OK, Json(HealthStatus { status: "ok", db: true })),
+        Err(e) => {
+            error!(error = %e, "Health check : base injoignable");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(HealthStatus { status: "error", db: false }),
+            )
+        }
+    }
+}
+
+async fn querylog_ws(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
+    let events = state.dns.subscribe_queries();
+    ws.on_upgrade(move |socket| stream_queries(socket, events))
+}
+
+async fn stream_queries(mut socket: WebSocket, mut events: broadcast::Receiver<QueryEvent>) {
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let Ok(json) = serde_json::to_string(&event) else { continue };
+                    if socket.send(Message::Text(json)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "Client WebSocket trop lent, événements perdus");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            // Les messages du client sont ignorés, seule la fermeture compte
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+async fn get_stats(State(state): State<AppState>) -> Json<StatsSnapshot> {
+    Json(state.dns.stats())
+}
+
+// Format texte Prometheus
+async fn get_metrics(State(state): State<AppState>) -> String {
+    state.metrics.render()
+}
+
+async fn get_top_blocked_domains(
+    Query(params): Query<TopParams>,
+) -> Result<Json<Vec<DomainCount>>, StatusCode> {
+    get_top_blocked(params.limit()).await.map(Json).map_err(|e| {
+        error!(error = %e, "Erreur lors du calcul des domaines les plus bloqués");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+async fn get_top_clients_stats(
+    Query(params): Query<TopParams>,
+) -> Result<Json<Vec<ClientCount>>, StatusCode> {
+    get_top_clients(params.limit()).await.map(Json).map_err(|e| {
+        error!(error = %e, "Erreur lors du calcul des clients les plus actifs");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+// Requêtes bloquées et autorisées par tranche, pour les graphiques du dashboard
+async fn get_stats_timeseries(
+    Query(params): Query<TimeseriesParams>,
+) -> Result<Json<Vec<TimeBucket>>, StatusCode> {
+    let window = params
+        .window
+        .unwrap_or(DEFAULT_TIMESERIES_WINDOW)
+        .clamp(1, MAX_TIMESERIES_WINDOW);
+    get_query_timeseries(params.interval.duration(), window)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            error!(error = %e, "Erreur lors du calcul de la série temporelle");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+#[derive(Serialize)]
+pub struct ClientHostname {
+    pub client_ip: String,
+    pub hostname: Option<String>,
+    pub count: i64,
+}
+
+// Clients les plus actifs avec leur nom d'hôte, résolus en parallèle
+pub async fn get_clients_with_hostnames(
+    dns: &dyn DnsHandle,
+    limit: u64,
+) -> anyhow::Result<Vec<ClientHostname>> {
+    let clients = get_top_clients(limit).await?;
+    let lookups = clients.into_iter().map(|client| async move {
+        let hostname = match client.client_ip.parse::<IpAddr>() {
+            Ok(ip) => dns.reverse_lookup(ip).await,
+            Err(_) => None,
+        };
+        ClientHostname {
+            client_ip: client.client_ip,
+            hostname,
+            count: client.count,
+        }
+    });
+    Ok(join_all(lookups).await)
+}
+
+async fn get_clients_stats(
+    State(state): State<AppState>,
+    Query(params): Query<TopParams>,
+) -> Result<Json<Vec<ClientHostname>>, StatusCode> {
+    get_clients_with_hostnames(state.dns.as_ref(), params.limit())
+        .await
+        .map(Json)
+        .map_err(|e| {
+            error!(error = format!("{:#}", e), "Erreur lors de la liste des clients");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+async fn disable_blocking(
+    State(state): State<AppState>,
+    Json(payload): Json<DisableBlocking>,
+) -> StatusCode {
+    if payload.seconds == 0 {
+        return StatusCode::BAD_REQUEST;
+    }
+    state.dns.disable_blocking(Duration::from_secs(payload.seconds));
+    StatusCode::NO_CONTENT
+}
+
+async fn enable_blocking(State(state): State<AppState>) -> StatusCode {
+    state.dns.enable_blocking();
+    StatusCode::NO_CONTENT
+}
+
+// ?domain=example.com&status=cached&source=forwarded : status est l'issue de
+// la requête, source l'étape qui a répondu (cache, forwarded, blocked, local,
+// rate_limited, refused ou error). Les refus du rate limit sont échantillonnés
+async fn get_querylog(
+    Query(params): Query<QueryLogParams>,
+) -> Result<Json<QueryLogPage>, StatusCode> {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_QUERYLOG_LIMIT)
+        .min(MAX_QUERYLOG_LIMIT);
+    let offset = params.offset.unwrap_or(0);
+    let domain = match params.domain {
+        Some(domain) => Some(normalize_domain(&domain).map_err(|_| StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+    let filter = QueryLogFilter {
+        domain,
+        status: params.status,
+        source: params.source,
+    };
+
+    match get_query_log(limit, offset, &filter).await {
+        Ok((entries, total)) => Ok(Json(QueryLogPage { total, limit, offset, entries })),
+        Err(e) => {
+            error!(error = %e, "Erreur lors de la lecture du journal");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn flush_cache(
+    State(state): State<AppState>,
+    Query(params): Query<FlushCacheParams>,
+) -> Result<Json<EvictedCount>, StatusCode> {
+    let domain = match params.domain {
+        Some(domain) => Some(normalize_domain(&domain).map_err(|_| StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+    let evicted = state.dns.flush_cache(domain.as_deref());
+    info!(evicted, domain = domain.as_deref(), "Cache DNS vidé");
+    Ok(Json(EvictedCount { evicted }))
+}
+
+// Pourquoi un domaine est bloqué (ou non), sans passer par le journal
+async fn check_domain(
+    State(state): State<AppState>,
+    Query(params): Query<CheckParams>,
+) -> Result<Json<DomainCheck>, StatusCode> {
+    let domain = normalize_domain(&params.domain).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let client = params.client.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    Ok(Json(state.dns.check_domain(client, &domain)))
+}
+
+async fn get_debug_cache(State(state): State<AppState>) -> Json<Vec<CacheEntryInfo>> {
+    Json(state.dns.cache_snapshot())
+}
+
+async fn delete_querylog(
+    Query(params): Query<ClearQueryLogParams>,
+) -> Result<Json<DeletedCount>, StatusCode> {
+    match clear_query_log(params.before).await {
+        Ok(deleted) => {
+            info!(deleted, before = params.before, "Journal des requêtes vidé");
+            Ok(Json(DeletedCount { deleted }))
+        }
+        Err(e) => {
+            error!(error = %e, "Erreur lors de la suppression du journal");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub const DEFAULT_API_ADDR: &str = "0.0.0.0:4000";
+pub const DEFAULT_CORS_ORIGIN: &str = "http://localhost:3000";
+
+pub struct ApiConfig {
+    pub addr: SocketAddr,
+    // Origines autorisées à appeler l'API depuis un navigateur (dashboard)
+    pub cors_origins: Vec<String>,
+    // Sans clé, l'API reste ouverte comme avant
+    pub api_key: Option<String>,
+    // Les GET restent accessibles sans clé, sauf le journal des requêtes, les
+    // statistiques par client et le debug ; les modifications l'exigent
+    pub public_reads: bool,
+    // Mode unifié : le dashboard est servi ici et l'API passe sous /api
+    pub frontend: Option<frontend::FrontendHandler>,
+    pub build: BuildInfo,
+    // Démarrage du processus, pour l'uptime de /version
+    pub started: Instant,
+    // Téléchargement des sources ajoutées depuis l'API
+    pub http: sources::HttpClient,
+}
+
+// Version du binaire, renseignée par son script de build
+#[derive(Clone, Copy)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    // Absent pour un build hors d'un dépôt git
+    pub commit: Option<&'static str>,
+    // Secondes Unix
+    pub built_at: u64,
+}
+
+const API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+#[derive(Clone)]
+struct ApiAuth {
+    key: Arc<str>,
+    public_reads: bool,
+}
+
+// Comparaison en temps constant, pour ne pas révéler la clé octet par octet
+fn key_matches(provided: &[u8], expected: &[u8]) -> bool {
+    provided.len() == expected.len()
+        && provided.iter().zip(expected).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+// Lectures qui exposent les clients ou les domaines qu'ils visitent : la clé
+// reste exigée avec `public_reads`
+const PRIVATE_READS: [&str; 4] = ["/querylog", "/ws/querylog", "/stats/clients", "/stats/top-clients"];
+
+fn is_private_read(path: &str) -> bool {
+    PRIVATE_READS.contains(&path) || path.starts_with("/debug/")
+}
+
+async fn require_api_key(State(auth): State<ApiAuth>, req: Request, next: Next) -> Response {
+    // Les sondes de santé n'ont pas la clé, pas plus que les clients DoH,
+    // ouverts comme le port 53
+    let path = req.uri().path();
+    let exempt = path == "/health"
+        || path == "/dns-query"
+        || (auth.public_reads && req.method() == Method::GET && !is_private_read(path));
+    let authorized = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .is_some_and(|key| key_matches(key.as_bytes(), auth.key.as_bytes()));
+
+    if exempt || authorized {
+        next.run(req).await
+    } else {
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+fn cors_layer(origins: &[String]) -> anyhow::Result<CorsLayer> {
+    let origins = origins
+        .iter()
+        .map(|o| {
+            HeaderValue::from_str(o).map_err(|_| anyhow::anyhow!("Origine CORS invalide: {}", o))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE])
+        .allow_headers([header::CONTENT_TYPE, API_KEY_HEADER]))
+}
+
+pub async fn run_api(config: ApiConfig, dns: Arc<dyn DnsHandle>) -> anyhow::Result<()> {
+    // Les compteurs du serveur DNS sont relevés par ce recorder global
+    let metrics = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full("rusthole_upstream_latency_seconds".to_string()),
+            LATENCY_BUCKETS,
+        )?
+        .install_recorder()?;
+    let addr = config.addr;
+    let unified = config.frontend.is_some();
+    let app = router(config, dns, metrics)?;
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 4000));
     let listener = TcpListener::bind(addr).await
-        .map_err(|e| anyhow::anyhow!("Impossible de lier le port 4000: {}. Le port est peut-être déjà utilisé.", e))?;
+        .map_err(|e| anyhow::anyhow!("Impossible de lier {}: {}. Le port est peut-être déjà utilisé.", addr, e))?;
 
-    println!("<API> Serveur API démarré sur 0.0.0.0:4000");
-    axum::serve(listener, app).await?;
+    if unified {
+        info!(addr = %addr, "Serveur API et dashboard démarré (API sous /api)");
+    } else {
+        info!(addr = %addr, "Serveur API démarré");
+    }
+    // Adresse du client nécessaire pour DoH (groupes, limite de débit)
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
     Ok(())
 }
+
+// Routes et couches de l'API, sans le recorder global ni l'écoute
+fn router(
+    config: ApiConfig,
+    dns: Arc<dyn DnsHandle>,
+    metrics: PrometheusHandle,
+) -> anyhow::Result<Router> {
+    let state = AppState {
+        dns,
+        metrics,
+        build: config.build,
+        started: config.started,
+        http: config.http,
+    };
+
+    let mut app = Router::new()
+        .route("/blocklist", get(get_blocked_domains).post(create_blocked_domain))
+        .route(
+            "/blocklist/import",
+            post(import_blocked_domains).layer(DefaultBodyLimit::max(MAX_IMPORT_SIZE)),
+        )
+        .route("/blocklist/export", get(export_blocked_domains))
+        .route("/blocklist/search", get(search_blocklist))
+        .route("/blocklist/regex", get(get_regex_rules).post(create_regex_rule))
+        .route("/blocklist/regex/:id", delete(remove_regex_rule))
+        .route("/blocklist/sources", get(get_blocklist_sources).post(create_blocklist_source))
+        .route(
+            "/blocklist/sources/:id",
+            patch(update_blocklist_source).delete(remove_blocklist_source),
+        )
+        .route("/blocklist/categories", get(get_categories))
+        .route("/blocklist/categories/:name", patch(update_category))
+        .route("/blocklist/:id", delete(remove_blocked_domain))
+        .route("/local-records", get(get_local_records).post(create_local_record))
+        .route(
+            "/local-records/:id",
+            put(replace_local_record).delete(remove_local_record),
+        )
+        .route("/health", get(health))
+        .route("/version", get(get_version))
+        .route("/stats", get(get_stats))
+        .route("/metrics", get(get_metrics))
+        .route("/stats/top-blocked", get(get_top_blocked_domains))
+        .route("/stats/top-clients", get(get_top_clients_stats))
+        .route("/stats/timeseries", get(get_stats_timeseries))
+        .route("/stats/clients", get(get_clients_stats))
+        .route("/disable", post(disable_blocking))
+        .route("/enable", post(enable_blocking))
+        .route("/querylog", get(get_querylog).delete(delete_querylog))
+        .route("/ws/querylog", get(querylog_ws))
+        .route("/dns-query", get(doh::dns_query_get).post(doh::dns_query_post))
+        .route("/check", get(check_domain))
+        .route("/cache/flush", post(flush_cache))
+        .route("/debug/cache", get(get_debug_cache));
+
+    match config.api_key {
+        Some(key) => {
+            let auth = ApiAuth {
+                key: key.into(),
+                public_reads: config.public_reads,
+            };
+            app = app.layer(middleware::from_fn_with_state(auth, require_api_key));
+            info!(public_reads = config.public_reads, "Authentification par clé API activée");
+        }
+        None => info!("Aucune clé API configurée, l'API est ouverte"),
+    }
+    let app = app.with_state(state);
+    // La clé API ne protège que /api : le dashboard se charge sans elle
+    let app = match config.frontend {
+        Some(handler) => Router::new()
+            .nest("/api", app)
+            .fallback(move |uri| frontend::serve(handler, uri)),
+        None => app,
+    };
+    // CORS à l'extérieur : les requêtes préflight n'ont pas la clé. La
+    // compression suit l'Accept-Encoding du client (brotli, sinon gzip)
+    Ok(app
+        .layer(CompressionLayer::new())
+        .layer(cors_layer(&config.cors_origins)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_hole_db::testing::test_db;
+    use rust_hole_db::{delete_blocked_domain, insert_blocked_domain, log_queries, NewQueryLog};
+    use serde_json::{json, Value};
+    use std::sync::Mutex;
+
+    // Serveur DNS simulé : la blocklist passe par la base, le reste est noté
+    struct MockDns {
+        disabled_for: Mutex<Option<Duration>>,
+        flushed: Mutex<Vec<Option<String>>>,
+        events: broadcast::Sender<QueryEvent>,
+    }
+
+    impl Default for MockDns {
+        fn default() -> Self {
+            Self {
+                disabled_for: Mutex::new(None),
+                flushed: Mutex::new(Vec::new()),
+                events: broadcast::channel(16).0,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl DnsHandle for MockDns {
+        async fn reload_blocklist(&self) -> anyhow::Result<usize> {
+            Ok(0)
+        }
+
+        async fn add_domain_live(&self, domain: &str) -> anyhow::Result<Option<BlockedDomainModel>> {
+            Ok(insert_blocked_domain(domain).await?)
+        }
+
+        async fn remove_domain_live(&self, id: u32) -> anyhow::Result<bool> {
+            Ok(delete_blocked_domain(id).await?.is_some())
+        }
+
+        fn stats(&self) -> StatsSnapshot {
+            let disabled_for = *self.disabled_for.lock().unwrap();
+            StatsSnapshot {
+                total_queries: 10,
+                blocked_queries: 4,
+                cache_hits: 3,
+                negative_cache_hits: 0,
+                forwarded_queries: 3,
+                local_queries: 0,
+                servfail_queries: 0,
+                refused_queries: 0,
+                formerr_queries: 0,
+                rate_limited_queries: 0,
+                dnssec_failures: 0,
+                leader_queries: 3,
+                coalesced_queries: 0,
+                percent_blocked: 40.0,
+                query_types: BTreeMap::new(),
+                upstream_latency: LatencySnapshot::default(),
+                cache_latency: LatencySnapshot::default(),
+                blocklist: BlocklistInfo::default(),
+                blocking_enabled: disabled_for.is_none(),
+                blocking_disabled_secs: disabled_for.map(|d| d.as_secs()),
+            }
+        }
+
+        fn disable_blocking(&self, duration: Duration) {
+            *self.disabled_for.lock().unwrap() = Some(duration);
+        }
+
+        fn enable_blocking(&self) {
+            *self.disabled_for.lock().unwrap() = None;
+        }
+
+        fn subscribe_queries(&self) -> broadcast::Receiver<QueryEvent> {
+            self.events.subscribe()
+        }
+
+        async fn reverse_lookup(&self, _ip: IpAddr) -> Option<String> {
+            None
+        }
+
+        async fn handle_query(&self, _query: &[u8], _client: IpAddr) -> anyhow::Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn cache_snapshot(&self) -> Vec<CacheEntryInfo> {
+            Vec::new()
+        }
+
+        fn flush_cache(&self, domain: Option<&str>) -> usize {
+            self.flushed.lock().unwrap().push(domain.map(str::to_string));
+            1
+        }
+
+        fn check_domain(&self, _client: IpAddr, _domain: &str) -> DomainCheck {
+            DomainCheck {
+                blocked: false,
+                matched_rule: None,
+                source: None,
+                allowlisted: false,
+            }
+        }
+    }
+
+    // API servie sur un port local éphémère
+    struct TestApi {
+        base: String,
+        client: reqwest::Client,
+        dns: Arc<MockDns>,
+    }
+
+    impl TestApi {
+        async fn start() -> Self {
+            Self::with_key(None, false).await
+        }
+
+        async fn with_key(api_key: Option<&str>, public_reads: bool) -> Self {
+            test_db().await;
+            let dns = Arc::new(MockDns::default());
+            let config = ApiConfig {
+                addr: "127.0.0.1:0".parse().unwrap(),
+                cors_origins: vec![DEFAULT_CORS_ORIGIN.to_string()],
+                api_key: api_key.map(str::to_string),
+                public_reads,
+                frontend: None,
+                build: BuildInfo {
+                    version: "test",
+                    commit: None,
+                    built_at: 0,
+                },
+                started: Instant::now(),
+                http: sources::http_client().unwrap(),
+            };
+            let metrics = PrometheusBuilder::new().build_recorder().handle();
+            let app = router(config, dns.clone(), metrics).unwrap();
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let base = format!("http://{}", listener.local_addr().unwrap());
+            tokio::spawn(async move {
+                axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await
+            });
+            Self {
+                base,
+                client: reqwest::Client::new(),
+                dns,
+            }
+        }
+
+        fn request(&self, method: Method, path: &str) -> reqwest::RequestBuilder {
+            self.client.request(method, format!("{}{}", self.base, path))
+        }
+
+        async fn send(&self, method: Method, path: &str, body: Option<Value>) -> (StatusCode, Value) {
+            let mut req = self.request(method, path);
+            if let Some(body) = body {
+                req = req.header(header::CONTENT_TYPE, "application/json").body(body.to_string());
+            }
+            read(req).await
+        }
+    }
+
+    // Statut et corps JSON (`Null` si vide)
+    async fn read(req: reqwest::RequestBuilder) -> (StatusCode, Value) {
+        let resp = req.send().await.unwrap();
+        let status = resp.status();
+        let text = resp.text().await.unwrap();
+        let body = if text.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_str(&text).unwrap_or(Value::String(text))
+        };
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn create_blocked_domain_normalizes_and_rejects_duplicates() {
+        let api = TestApi::start().await;
+        let (status, body) = api
+            .send(Method::POST, "/blocklist", Some(json!({ "domain": " Ads.Create.API.test. " })))
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["domain"], "ads.create.api.test");
+
+        let (status, _) = api
+            .send(Method::POST, "/blocklist", Some(json!({ "domain": "ads.create.api.test" })))
+            .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn create_blocked_domain_rejects_invalid_input() {
+        let api = TestApi::start().await;
+        for domain in ["", "not a domain", "-bad.api.test", "a..api.test"] {
+            let (status, _) = api
+                .send(Method::POST, "/blocklist", Some(json!({ "domain": domain })))
+                .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{:?}", domain);
+        }
+        let (status, _) = api.send(Method::POST, "/blocklist", Some(json!({}))).await;
+        assert!(status.is_client_error());
+    }
+
+    #[tokio::test]
+    async fn delete_blocked_domain_by_id() {
+        let api = TestApi::start().await;
+        let (_, created) = api
+            .send(Method::POST, "/blocklist", Some(json!({ "domain": "delete.api.test" })))
+            .await;
+        let path = format!("/blocklist/{}", created["id"]);
+
+        let (status, _) = api.send(Method::DELETE, &path, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = api.send(Method::DELETE, &path, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        // Ajoutable à nouveau une fois supprimé
+        let (status, _) = api
+            .send(Method::POST, "/blocklist", Some(json!({ "domain": "delete.api.test" })))
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    fn log_entry(timestamp: i64, domain: &str, status: QueryStatus, source: QuerySource) -> NewQueryLog {
+        NewQueryLog {
+            timestamp,
+            client_ip: "192.168.1.50".to_string(),
+            domain: domain.to_string(),
+            query_type: "A".to_string(),
+            status,
+            source,
+        }
+    }
+
+    #[tokio::test]
+    async fn querylog_pages_newest_first() {
+        let api = TestApi::start().await;
+        let entries = (0..5)
+            .map(|i| log_entry(1_000 + i, "page.querylog.test", QueryStatus::Allowed, QuerySource::Forwarded))
+            .collect();
+        log_queries(entries).await.unwrap();
+
+        let (status, page) = api
+            .send(Method::GET, "/querylog?domain=page.querylog.test&limit=2&offset=1", None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page["total"], 5);
+        assert_eq!(page["limit"], 2);
+        let entries = page["entries"].as_array().unwrap();
+        let timestamps: Vec<_> = entries.iter().map(|e| e["timestamp"].as_i64().unwrap()).collect();
+        assert_eq!(timestamps, [1_003, 1_002]);
+
+        let (_, page) = api.send(Method::GET, "/querylog?limit=100000", None).await;
+        assert_eq!(page["limit"], MAX_QUERYLOG_LIMIT);
+    }
+
+    #[tokio::test]
+    async fn querylog_filters_by_status_and_source() {
+        let api = TestApi::start().await;
+        let domain = "filter.querylog.test";
+        log_queries(vec![
+            log_entry(2_000, domain, QueryStatus::Allowed, QuerySource::Forwarded),
+            log_entry(2_001, domain, QueryStatus::Cached, QuerySource::Cache),
+            log_entry(2_002, domain, QueryStatus::Refused, QuerySource::RateLimited),
+            log_entry(2_003, domain, QueryStatus::Refused, QuerySource::Refused),
+        ])
+        .await
+        .unwrap();
+
+        let (_, page) = api
+            .send(Method::GET, "/querylog?domain=Filter.QueryLog.test.&status=refused", None)
+            .await;
+        assert_eq!(page["total"], 2);
+        let (_, page) = api
+            .send(Method::GET, &format!("/querylog?domain={}&source=rate_limited", domain), None)
+            .await;
+        assert_eq!(page["total"], 1);
+        assert_eq!(page["entries"][0]["source"], "rate_limited");
+        let (_, page) = api
+            .send(Method::GET, &format!("/querylog?domain={}&status=cached&source=forwarded", domain), None)
+            .await;
+        assert_eq!(page["total"], 0);
+
+        for query in ["domain=not%20a%20domain", "status=bogus", "source=bogus"] {
+            let (status, _) = api.send(Method::GET, &format!("/querylog?{}", query), None).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn disable_then_enable_blocking() {
+        let api = TestApi::start().await;
+        let (status, _) = api.send(Method::POST, "/disable", Some(json!({ "seconds": 300 }))).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(*api.dns.disabled_for.lock().unwrap(), Some(Duration::from_secs(300)));
+        let (_, stats) = api.send(Method::GET, "/stats", None).await;
+        assert_eq!(stats["blocking_enabled"], false);
+        assert_eq!(stats["blocking_disabled_secs"], 300);
+
+        let (status, _) = api.send(Method::POST, "/enable", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(*api.dns.disabled_for.lock().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn disable_requires_a_duration() {
+        let api = TestApi::start().await;
+        let (status, _) = api.send(Method::POST, "/disable", Some(json!({ "seconds": 0 }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = api.send(Method::POST, "/disable", Some(json!({}))).await;
+        assert!(status.is_client_error());
+        assert_eq!(*api.dns.disabled_for.lock().unwrap(), None);
+    }
+
+    fn local_record(domain: &str, record_type: &str, value: &str) -> Option<Value> {
+        Some(json!({ "domain": domain, "record_type": record_type, "value": value }))
+    }
+
+    #[tokio::test]
+    async fn local_records_are_validated_against_their_type() {
+        let api = TestApi::start().await;
+        let (status, record) = api
+            .send(Method::POST, "/local-records", local_record("NAS.Local.API.test", "a", " 192.168.1.10 "))
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(record["domain"], "nas.local.api.test");
+        assert_eq!(record["record_type"], "A");
+        assert_eq!(record["value"], "192.168.1.10");
+        assert_eq!(record["ttl"], DEFAULT_LOCAL_TTL);
+
+        let invalid = [("A", "::1"), ("AAAA", "192.168.1.10"), ("CNAME", "not a name"), ("MX", "mail.test")];
+        for (record_type, value) in invalid {
+            let (status, _) = api
+                .send(Method::POST, "/local-records", local_record("bad.local.api.test", record_type, value))
+                .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{} {}", record_type, value);
+        }
+    }
+
+    #[tokio::test]
+    async fn local_records_can_be_replaced_and_removed() {
+        let api = TestApi::start().await;
+        let (_, record) = api
+            .send(Method::POST, "/local-records", local_record("printer.local.api.test", "A", "192.168.1.20"))
+            .await;
+        let path = format!("/local-records/{}", record["id"]);
+
+        let (status, record) = api
+            .send(
+                Method::PUT,
+                &path,
+                Some(json!({
+                    "domain": "printer.local.api.test",
+                    "record_type": "CNAME",
+                    "value": "NAS.local.api.test.",
+                    "ttl": 60,
+                })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(record["value"], "nas.local.api.test");
+        assert_eq!(record["ttl"], 60);
+
+        let (status, _) = api.send(Method::DELETE, &path, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = api.send(Method::DELETE, &path, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = api
+            .send(Method::PUT, &path, local_record("printer.local.api.test", "A", "192.168.1.20"))
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn health_reports_the_database() {
+        let api = TestApi::start().await;
+        let (status, body) = api.send(Method::GET, "/health", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "status": "ok", "db": true }));
+    }
+
+    #[tokio::test]
+    async fn api_key_is_required_when_configured() {
+        let api = TestApi::with_key(Some("secret"), false).await;
+        let (status, _) = api.send(Method::GET, "/stats", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = read(api.request(Method::GET, "/stats").header("x-api-key", "secreT")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = read(api.request(Method::GET, "/stats").header("x-api-key", "secret")).await;
+        assert_eq!(status, StatusCode::OK);
+        // Sondes de santé sans clé
+        let (status, _) = api.send(Method::GET, "/health", None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn public_reads_only_open_get_requests() {
+        let api = TestApi::with_key(Some("secret"), true).await;
+        let (status, _) = api.send(Method::GET, "/stats", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = api.send(Method::POST, "/enable", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        // Clients et domaines visités restent derrière la clé
+        for path in ["/querylog", "/ws/querylog", "/stats/clients", "/stats/top-clients", "/debug/cache"] {
+            let (status, _) = api.send(Method::GET, path, None).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", path);
+        }
+        let (status, _) = read(api.request(Method::GET, "/querylog").header("x-api-key", "secret")).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = read(api.request(Method::POST, "/enable").header("x-api-key", "secret")).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn cors_preflight_needs_no_api_key() {
+        let api = TestApi::with_key(Some("secret"), false).await;
+        let resp = api
+            .request(Method::OPTIONS, "/blocklist")
+            .header(header::ORIGIN, DEFAULT_CORS_ORIGIN)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "x-api-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], DEFAULT_CORS_ORIGIN);
+    }
+}
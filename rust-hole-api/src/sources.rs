@@ -0,0 +1,89 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::{debug, info, warn};
+
+use rust_hole_db::models::blocklist_sources::Model as BlocklistSourceModel;
+use rust_hole_db::{get_all_blocklist_sources, record_source_error, replace_source_domains};
+
+use crate::hosts::parse_hosts;
+
+pub use reqwest::Client as HttpClient;
+
+const FETCH_ATTEMPTS: u32 = 3;
+// Doublé à chaque nouvel essai : 1 s, puis 2 s
+const FETCH_BACKOFF: Duration = Duration::from_secs(1);
+// Par essai : un serveur qui ne répond plus ne bloque pas les mises à jour
+const FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+const FETCH_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn unix_now() -> anyhow::Result<i64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64)
+}
+
+// Client partagé par les mises à jour périodiques et l'API, qui réutilisent
+// ainsi ses connexions
+pub fn http_client() -> reqwest::Result<HttpClient> {
+    HttpClient::builder()
+        .timeout(FETCH_TIMEOUT)
+        .connect_timeout(FETCH_CONNECT_TIMEOUT)
+        .build()
+}
+
+// Réessaie les erreurs réseau et serveur (5xx). Une 4xx (liste retirée, URL
+// erronée) ne se corrige pas en réessayant
+async fn fetch_list(client: &reqwest::Client, url: &str) -> reqwest::Result<String> {
+    let mut delay = FETCH_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let result = async { client.get(url).send().await?.error_for_status()?.text().await }.await;
+        match result {
+            Err(e) if attempt < FETCH_ATTEMPTS && !e.status().is_some_and(|s| s.is_client_error()) => {
+                debug!(url, attempt, retry_in = ?delay, error = %e, "Échec du téléchargement, nouvel essai");
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Télécharge une liste au format hosts et remplace les domaines de la source.
+// En cas d'échec, les domaines déjà ingérés restent en place et l'erreur est
+// enregistrée sur la source
+pub async fn refresh_source(
+    client: &reqwest::Client,
+    source: &BlocklistSourceModel,
+) -> anyhow::Result<usize> {
+    let body = match fetch_list(client, &source.url).await {
+        Ok(body) => body,
+        Err(e) => {
+            let error = anyhow::Error::from(e);
+            if let Err(db_error) = record_source_error(source.id, &format!("{:#}", error), unix_now()?).await {
+                warn!(url = %source.url, error = %db_error, "Impossible d'enregistrer l'échec de la source");
+            }
+            return Err(error);
+        }
+    };
+
+    let parsed = parse_hosts(&body);
+    let count = replace_source_domains(source.id, parsed.domains, unix_now()?).await?;
+
+    info!(url = %source.url, domains = count, skipped = parsed.skipped, "Source mise à jour");
+    Ok(count)
+}
+
+// Met à jour toutes les sources activées ; une source en échec n'empêche pas
+// les autres. Retourne le nombre de sources mises à jour.
+pub async fn refresh_all_sources(client: &HttpClient) -> anyhow::Result<usize> {
+    let sources = get_all_blocklist_sources().await?;
+
+    let mut refreshed = 0;
+    for source in sources.iter().filter(|s| s.enabled) {
+        match refresh_source(client, source).await {
+            Ok(_) => refreshed += 1,
+            Err(e) => warn!(url = %source.url, error = format!("{:#}", e), "Échec de mise à jour de la source, domaines précédents conservés"),
+        }
+    }
+    Ok(refreshed)
+}
@@ -0,0 +1,83 @@
+// Trie de la blocklist contre l'ancien parcours linéaire, sur 1M d'entrées :
+//   cargo bench -p rust-hole-core --bench trie
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+// Seules les méthodes mesurées servent ici, et les tests du module ne
+// tournent pas sans harnais
+#[allow(dead_code, unused_imports)]
+#[path = "../src/dns/trie.rs"]
+mod trie;
+
+use trie::DomainTrie;
+
+const ENTRIES: usize = 1_000_000;
+const LINEAR_QUERIES: usize = 50;
+const TRIE_QUERIES: usize = 1_000_000;
+
+// Liste synthétique, du même ordre que les grosses listes publiques
+fn blocklist() -> Vec<String> {
+    (0..ENTRIES)
+        .map(|i| match i % 4 {
+            0 => format!("ads{i}.example.com"),
+            1 => format!("tracker{i}.net"),
+            2 => format!("cdn.metrics{i}.org"),
+            _ => format!("pixel.s{}.t{i}.io", i % 97),
+        })
+        .collect()
+}
+
+// Moitié de sous-domaines bloqués, moitié de noms absents de la liste
+fn queries(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| {
+            let n = (i * 7919) % ENTRIES;
+            if i % 2 == 0 {
+                format!("www.tracker{}.net", n - n % 4 + 1)
+            } else {
+                format!("www.site{n}.fr")
+            }
+        })
+        .collect()
+}
+
+// Ce que faisait is_blocked avant le trie
+fn linear_match(list: &[String], domain: &str) -> bool {
+    list.iter().any(|d| domain.ends_with(d.as_str()))
+}
+
+fn per_query(elapsed: Duration, count: usize) -> Duration {
+    elapsed / u32::try_from(count).unwrap()
+}
+
+fn main() {
+    let list = blocklist();
+
+    let start = Instant::now();
+    let trie: DomainTrie = list.iter().collect();
+    println!("construction du trie : {:?} ({} entrées)", start.elapsed(), trie.len());
+
+    let linear_queries = queries(LINEAR_QUERIES);
+    let start = Instant::now();
+    let mut linear_hits = 0;
+    for query in &linear_queries {
+        linear_hits += usize::from(linear_match(black_box(&list), black_box(query)));
+    }
+    let linear = per_query(start.elapsed(), LINEAR_QUERIES);
+
+    let trie_queries = queries(TRIE_QUERIES);
+    let start = Instant::now();
+    let mut trie_hits = 0;
+    for query in &trie_queries {
+        trie_hits += usize::from(black_box(&trie).matches(black_box(query)));
+    }
+    let lookup = per_query(start.elapsed(), TRIE_QUERIES);
+
+    // Mêmes réponses sur les requêtes communes
+    for query in &linear_queries {
+        assert_eq!(linear_match(&list, query), trie.matches(query), "{query}");
+    }
+    println!("parcours linéaire : {linear:?} par requête ({linear_hits}/{LINEAR_QUERIES} bloquées)");
+    println!("trie : {lookup:?} par requête ({trie_hits}/{TRIE_QUERIES} bloquées)");
+    println!("rapport : x{:.0}", linear.as_secs_f64() / lookup.as_secs_f64());
+}
@@ -0,0 +1,39 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Informations de build exposées par GET /version : commit git (absent hors
+// d'un dépôt, archive des sources par exemple) et date du build, en secondes
+// Unix. SOURCE_DATE_EPOCH fixe la date pour les builds reproductibles
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=RUSTHOLE_GIT_COMMIT={}", commit);
+
+    let built_at = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        });
+    println!("cargo:rustc-env=RUSTHOLE_BUILD_TIME={}", built_at);
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    // Relancé à chaque commit (la branche courante avance) et changement de branche (HEAD)
+    let git_dir = Path::new("../.git");
+    println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+    if let Ok(head) = std::fs::read_to_string(git_dir.join("HEAD")) {
+        if let Some(branch) = head.trim().strip_prefix("ref: ") {
+            println!("cargo:rerun-if-changed={}", git_dir.join(branch).display());
+        }
+    }
+}
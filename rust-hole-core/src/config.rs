@@ -0,0 +1,534 @@
+use std::fmt::Display;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use hickory_proto::rr::Name;
+use ipnet::IpNet;
+use serde::{Deserialize, Deserializer};
+use tracing::info;
+
+use rust_hole_api::{DEFAULT_API_ADDR, DEFAULT_CORS_ORIGIN};
+use rust_hole_db::{
+    DEFAULT_BUSY_TIMEOUT_SECS, DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_MAX_CONNECTIONS,
+    DEFAULT_MIN_CONNECTIONS,
+};
+
+use crate::dns::cache::{
+    DEFAULT_CACHE_SIZE, DEFAULT_MAX_TTL, DEFAULT_MIN_TTL, DEFAULT_NEGATIVE_TTL,
+    DEFAULT_SWEEP_INTERVAL_SECS,
+};
+use crate::dns::query_log::LogVerbosity;
+use crate::dns::rate_limit::DEFAULT_RATE_LIMIT_QPS;
+use crate::dns::server::{
+    BlockingMode, DEFAULT_BLOCKED_TTL, DEFAULT_DNS_ADDR, DEFAULT_SOA_MNAME, DEFAULT_SOA_RNAME,
+};
+use crate::dns::upstream::{
+    Protocol, ResolverStrategy, DEFAULT_UPSTREAM, DEFAULT_UPSTREAM_ATTEMPTS,
+    DEFAULT_UPSTREAM_TIMEOUT_SECS,
+};
+
+// Cherché dans le dossier courant quand RUSTHOLE_CONFIG n'est pas défini
+const DEFAULT_CONFIG_PATH: &str = "rusthole.toml";
+const DEFAULT_HTTP_ADDR: &str = "0.0.0.0:3000";
+const DEFAULT_SOURCES_REFRESH_HOURS: u64 = 24;
+// Durée de conservation du journal des requêtes, 0 pour tout garder
+const DEFAULT_QUERY_LOG_RETENTION_DAYS: u64 = 7;
+
+// ================= Configuration =================
+// Fichier TOML, puis variables d'environnement qui priment sur lui.
+// Chaque clé absente garde sa valeur par défaut, un fichier absent aussi
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database: DatabaseSection,
+    pub dns: DnsSection,
+    pub upstream: UpstreamSection,
+    pub cache: CacheSection,
+    pub api: ApiSection,
+    pub http: HttpSection,
+    pub sources: SourcesSection,
+    pub query_log: QueryLogSection,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSection {
+    // Prioritaire sur `data_dir`
+    pub url: Option<String>,
+    // Dossier du fichier SQLite, ~/.local/share/rust-hole sous Linux par défaut
+    pub data_dir: Option<PathBuf>,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub connect_timeout_secs: u64,
+    pub busy_timeout_secs: u64,
+}
+
+impl Default for DatabaseSection {
+    fn default() -> Self {
+        Self {
+            url: None,
+            data_dir: None,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            min_connections: DEFAULT_MIN_CONNECTIONS,
+            connect_timeout_secs: DEFAULT_CONNECT_TIMEOUT_SECS,
+            busy_timeout_secs: DEFAULT_BUSY_TIMEOUT_SECS,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DnsSection {
+    pub addr: SocketAddr,
+    #[serde(deserialize_with = "from_str")]
+    pub blocking_mode: BlockingMode,
+    pub blocked_ttl: u32,
+    #[serde(deserialize_with = "from_str")]
+    pub soa_mname: Name,
+    #[serde(deserialize_with = "from_str")]
+    pub soa_rname: Name,
+    // Requêtes TXT bloquées : TXT « blocked by rust-hole: source=… »
+    pub blocked_txt_reason: bool,
+    // Requêtes par seconde et par client, 0 pour désactiver
+    pub rate_limit: u32,
+    // Sous-réseaux autorisés ("192.168.1.0/24"), tous si la liste est vide
+    pub allowed_clients: Vec<IpNet>,
+    // Requêtes ANY transmises telles quelles au lieu de la réponse RFC 8482
+    pub forward_any: bool,
+    pub ecs_passthrough: bool,
+    pub hosts_file: Option<PathBuf>,
+    // Port utilisé si `addr` est refusé faute de privilèges, pour les tests
+    // locaux. Jamais utilisé sans être configuré
+    pub fallback_port: Option<u16>,
+}
+
+impl Default for DnsSection {
+    fn default() -> Self {
+        Self {
+            addr: DEFAULT_DNS_ADDR.parse().expect("adresse DNS par défaut invalide"),
+            blocking_mode: BlockingMode::default(),
+            blocked_ttl: DEFAULT_BLOCKED_TTL,
+            soa_mname: Name::from_ascii(DEFAULT_SOA_MNAME).expect("SOA mname par défaut invalide"),
+            soa_rname: Name::from_ascii(DEFAULT_SOA_RNAME).expect("SOA rname par défaut invalide"),
+            blocked_txt_reason: false,
+            rate_limit: DEFAULT_RATE_LIMIT_QPS,
+            allowed_clients: Vec::new(),
+            forward_any: false,
+            ecs_passthrough: false,
+            hosts_file: None,
+            fallback_port: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamSection {
+    // "ip" ou "ip:port", le port par défaut dépend du protocole
+    pub servers: Vec<String>,
+    #[serde(deserialize_with = "from_str")]
+    pub protocol: Protocol,
+    pub tls_name: Option<String>,
+    pub timeout_secs: u64,
+    // Nombre de passes sur la liste des upstreams
+    pub retries: u32,
+    #[serde(deserialize_with = "from_str")]
+    pub strategy: ResolverStrategy,
+    // Exige la validation DNSSEC : les upstreams doivent être des résolveurs validants
+    pub dnssec: bool,
+    // Règles "suffixe=ip[:port]", par exemple "home.lan=192.168.1.1"
+    pub conditional_forward: Vec<String>,
+}
+
+impl Default for UpstreamSection {
+    fn default() -> Self {
+        Self {
+            servers: vec![DEFAULT_UPSTREAM.to_string()],
+            protocol: Protocol::default(),
+            tls_name: None,
+            timeout_secs: DEFAULT_UPSTREAM_TIMEOUT_SECS,
+            retries: DEFAULT_UPSTREAM_ATTEMPTS,
+            strategy: ResolverStrategy::default(),
+            dnssec: false,
+            conditional_forward: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheSection {
+    pub size: usize,
+    pub min_ttl: u32,
+    pub max_ttl: u32,
+    // Sans SOA dans la réponse, sinon le TTL du SOA (RFC 2308)
+    pub negative_ttl: u32,
+    pub cache_nxdomain: bool,
+    pub cache_nodata: bool,
+    pub sweep_secs: u64,
+}
+
+impl Default for CacheSection {
+    fn default() -> Self {
+        Self {
+            size: DEFAULT_CACHE_SIZE,
+            min_ttl: DEFAULT_MIN_TTL,
+            max_ttl: DEFAULT_MAX_TTL,
+            negative_ttl: DEFAULT_NEGATIVE_TTL,
+            cache_nxdomain: true,
+            cache_nodata: true,
+            sweep_secs: DEFAULT_SWEEP_INTERVAL_SECS,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiSection {
+    pub addr: SocketAddr,
+    pub cors_origins: Vec<String>,
+    pub api_key: Option<String>,
+    pub public_reads: bool,
+}
+
+impl Default for ApiSection {
+    fn default() -> Self {
+        Self {
+            addr: DEFAULT_API_ADDR.parse().expect("adresse API par défaut invalide"),
+            cors_origins: vec![DEFAULT_CORS_ORIGIN.to_string()],
+            api_key: None,
+            public_reads: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpSection {
+    pub addr: SocketAddr,
+    // Dashboard et API servis sur `addr`, l'API sous /api ; `api.addr` est
+    // alors ignoré
+    pub unified: bool,
+}
+
+impl Default for HttpSection {
+    fn default() -> Self {
+        Self {
+            addr: DEFAULT_HTTP_ADDR.parse().expect("adresse HTTP par défaut invalide"),
+            unified: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourcesSection {
+    pub refresh_hours: u64,
+}
+
+impl Default for SourcesSection {
+    fn default() -> Self {
+        Self {
+            refresh_hours: DEFAULT_SOURCES_REFRESH_HOURS,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueryLogSection {
+    pub retention_days: u64,
+    #[serde(deserialize_with = "from_str")]
+    pub verbosity: LogVerbosity,
+}
+
+impl Default for QueryLogSection {
+    fn default() -> Self {
+        Self {
+            retention_days: DEFAULT_QUERY_LOG_RETENTION_DAYS,
+            verbosity: LogVerbosity::default(),
+        }
+    }
+}
+
+impl Config {
+    // RUSTHOLE_CONFIG doit désigner un fichier existant, rusthole.toml peut manquer
+    pub fn load() -> anyhow::Result<Self> {
+        let mut config = match std::env::var_os("RUSTHOLE_CONFIG") {
+            Some(path) => Self::from_file(Path::new(&path), true)?,
+            None => Self::from_file(Path::new(DEFAULT_CONFIG_PATH), false)?,
+        };
+        config.apply_env()?;
+
+        if config.cache.min_ttl > config.cache.max_ttl {
+            return Err(anyhow::anyhow!("cache.min_ttl doit être inférieur à cache.max_ttl"));
+        }
+        Ok(config)
+    }
+
+    fn from_file(path: &Path, required: bool) -> anyhow::Result<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound && !required => {
+                info!("Aucun fichier de configuration, valeurs par défaut");
+                return Ok(Self::default());
+            }
+            Err(e) => {
+                return Err(anyhow::anyhow!("Lecture de {} impossible: {}", path.display(), e));
+            }
+        };
+        let config = toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Configuration {} invalide: {}", path.display(), e))?;
+        info!(path = %path.display(), "Configuration chargée");
+        Ok(config)
+    }
+
+    // Les variables historiques restent valables et priment sur le fichier
+    fn apply_env(&mut self) -> anyhow::Result<()> {
+        let db = &mut self.database;
+        env_option(&mut db.url, "DATABASE_URL")?;
+        env_option(&mut db.data_dir, "RUSTHOLE_DATA_DIR")?;
+        env(&mut db.max_connections, "RUSTHOLE_DB_MAX_CONNECTIONS")?;
+        env(&mut db.min_connections, "RUSTHOLE_DB_MIN_CONNECTIONS")?;
+        env(&mut db.connect_timeout_secs, "RUSTHOLE_DB_CONNECT_TIMEOUT")?;
+        env(&mut db.busy_timeout_secs, "RUSTHOLE_DB_BUSY_TIMEOUT")?;
+
+        let dns = &mut self.dns;
+        env(&mut dns.addr, "RUSTHOLE_DNS_ADDR")?;
+        env(&mut dns.blocking_mode, "RUSTHOLE_BLOCKING_MODE")?;
+        env(&mut dns.blocked_ttl, "RUSTHOLE_BLOCKED_TTL")?;
+        env(&mut dns.soa_mname, "RUSTHOLE_SOA_MNAME")?;
+        env(&mut dns.soa_rname, "RUSTHOLE_SOA_RNAME")?;
+        env(&mut dns.blocked_txt_reason, "RUSTHOLE_BLOCKED_TXT_REASON")?;
+        env(&mut dns.rate_limit, "RUSTHOLE_RATE_LIMIT")?;
+        env_list(&mut dns.allowed_clients, "RUSTHOLE_ALLOWED_CLIENTS")?;
+        env(&mut dns.forward_any, "RUSTHOLE_FORWARD_ANY")?;
+        env(&mut dns.ecs_passthrough, "RUSTHOLE_ECS_PASSTHROUGH")?;
+        env_option(&mut dns.hosts_file, "RUSTHOLE_HOSTS_FILE")?;
+        env_option(&mut dns.fallback_port, "RUSTHOLE_DNS_FALLBACK_PORT")?;
+
+        let upstream = &mut self.upstream;
+        env_list(&mut upstream.servers, "RUSTHOLE_UPSTREAM")?;
+        env(&mut upstream.protocol, "RUSTHOLE_UPSTREAM_PROTOCOL")?;
+        env_option(&mut upstream.tls_name, "RUSTHOLE_UPSTREAM_TLS_NAME")?;
+        env(&mut upstream.timeout_secs, "RUSTHOLE_UPSTREAM_TIMEOUT")?;
+        env(&mut upstream.retries, "RUSTHOLE_UPSTREAM_RETRIES")?;
+        env(&mut upstream.strategy, "RUSTHOLE_UPSTREAM_STRATEGY")?;
+        env(&mut upstream.dnssec, "RUSTHOLE_DNSSEC")?;
+        env_list(&mut upstream.conditional_forward, "RUSTHOLE_CONDITIONAL_FORWARD")?;
+
+        let cache = &mut self.cache;
+        env(&mut cache.size, "RUSTHOLE_CACHE_SIZE")?;
+        env(&mut cache.min_ttl, "RUSTHOLE_CACHE_MIN_TTL")?;
+        env(&mut cache.max_ttl, "RUSTHOLE_CACHE_MAX_TTL")?;
+        env(&mut cache.negative_ttl, "RUSTHOLE_CACHE_NEGATIVE_TTL")?;
+        env(&mut cache.cache_nxdomain, "RUSTHOLE_CACHE_NXDOMAIN")?;
+        env(&mut cache.cache_nodata, "RUSTHOLE_CACHE_NODATA")?;
+        env(&mut cache.sweep_secs, "RUSTHOLE_CACHE_SWEEP_SECS")?;
+
+        let api = &mut self.api;
+        env(&mut api.addr, "RUSTHOLE_API_ADDR")?;
+        env_list(&mut api.cors_origins, "RUSTHOLE_CORS_ORIGIN")?;
+        env_option(&mut api.api_key, "RUSTHOLE_API_KEY")?;
+        env(&mut api.public_reads, "RUSTHOLE_API_PUBLIC_READS")?;
+
+        env(&mut self.http.addr, "RUSTHOLE_HTTP_ADDR")?;
+        env(&mut self.http.unified, "RUSTHOLE_HTTP_UNIFIED")?;
+        env(&mut self.sources.refresh_hours, "RUSTHOLE_SOURCES_REFRESH_HOURS")?;
+        env(&mut self.query_log.retention_days, "RUSTHOLE_QUERY_LOG_RETENTION_DAYS")?;
+        env(&mut self.query_log.verbosity, "RUSTHOLE_QUERY_LOG")?;
+        Ok(())
+    }
+}
+
+// ---------- Variables d'environnement ----------
+
+fn env<T>(value: &mut T, name: &str) -> anyhow::Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(raw) = std::env::var(name) {
+        *value = raw
+            .parse()
+            .map_err(|e| anyhow::anyhow!("{} invalide: {}", name, e))?;
+    }
+    Ok(())
+}
+
+// Une variable vide désactive le réglage (RUSTHOLE_API_KEY= sans clé)
+fn env_option<T>(value: &mut Option<T>, name: &str) -> anyhow::Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match std::env::var(name) {
+        Ok(raw) if raw.is_empty() => *value = None,
+        Ok(raw) => {
+            *value = Some(
+                raw.parse()
+                    .map_err(|e| anyhow::anyhow!("{} invalide: {}", name, e))?,
+            )
+        }
+        Err(_) => {}
+    }
+    Ok(())
+}
+
+// Liste séparée par des virgules
+fn env_list<T>(value: &mut Vec<T>, name: &str) -> anyhow::Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(raw) = std::env::var(name) {
+        *value = raw
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse().map_err(|e| anyhow::anyhow!("{} invalide: {}", name, e)))
+            .collect::<anyhow::Result<_>>()?;
+    }
+    Ok(())
+}
+
+// Types configurables dont la syntaxe est celle de leur FromStr
+fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let raw = String::deserialize(deserializer)?;
+    raw.parse().map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Les variables d'environnement sont globales au processus
+    static ENV: Mutex<()> = Mutex::new(());
+
+    fn parse(toml: &str) -> anyhow::Result<Config> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    fn write_config(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("rusthole-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn missing_keys_keep_their_defaults() {
+        let config = parse(
+            r#"
+            [dns]
+            addr = "127.0.0.1:5353"
+            blocking_mode = "nullip"
+            allowed_clients = ["192.168.1.0/24"]
+
+            [upstream]
+            servers = ["9.9.9.9", "1.1.1.1:53"]
+            strategy = "fastest"
+
+            [cache]
+            max_ttl = 3600
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.dns.addr, "127.0.0.1:5353".parse().unwrap());
+        assert_eq!(config.dns.blocking_mode, BlockingMode::NullIp);
+        assert_eq!(config.dns.allowed_clients, ["192.168.1.0/24".parse::<IpNet>().unwrap()]);
+        assert_eq!(config.dns.rate_limit, DEFAULT_RATE_LIMIT_QPS);
+        assert_eq!(config.upstream.servers, ["9.9.9.9", "1.1.1.1:53"]);
+        assert_eq!(config.upstream.strategy, ResolverStrategy::Fastest);
+        assert_eq!(config.upstream.protocol, Protocol::Udp);
+        assert_eq!(config.cache.max_ttl, 3600);
+        assert_eq!(config.cache.size, DEFAULT_CACHE_SIZE);
+        assert_eq!(config.query_log.verbosity, LogVerbosity::default());
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let err = parse("[cache]\nmax_tll = 3600\n").unwrap_err();
+        assert!(err.to_string().contains("max_tll"), "{}", err);
+        assert!(parse("[dnss]\naddr = \"127.0.0.1:53\"\n").is_err());
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let err = parse("[dns]\nblocking_mode = \"sinkhole\"\n").unwrap_err();
+        assert!(err.to_string().contains("sinkhole"), "{}", err);
+        assert!(parse("[upstream]\nstrategy = \"random\"\n").is_err());
+        assert!(parse("[dns]\naddr = \"localhost\"\n").is_err());
+        assert!(parse("[cache]\nsize = -1\n").is_err());
+    }
+
+    #[test]
+    fn only_an_explicit_config_file_is_required() {
+        let missing = std::env::temp_dir().join("rusthole-absent.toml");
+        assert!(Config::from_file(&missing, true).is_err());
+        let config = Config::from_file(&missing, false).unwrap();
+        assert_eq!(config.sources.refresh_hours, DEFAULT_SOURCES_REFRESH_HOURS);
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let _env = ENV.lock().unwrap();
+        let path = write_config(
+            "env",
+            r#"
+            [upstream]
+            servers = ["9.9.9.9"]
+            [api]
+            api_key = "depuis-le-fichier"
+            [query_log]
+            verbosity = "verbose"
+            "#,
+        );
+        std::env::set_var("RUSTHOLE_CONFIG", &path);
+        std::env::set_var("RUSTHOLE_UPSTREAM", "1.1.1.1, 8.8.8.8:53,");
+        std::env::set_var("RUSTHOLE_API_KEY", "");
+        std::env::set_var("RUSTHOLE_RATE_LIMIT", "20");
+        let config = Config::load();
+        for name in ["RUSTHOLE_CONFIG", "RUSTHOLE_UPSTREAM", "RUSTHOLE_API_KEY", "RUSTHOLE_RATE_LIMIT"] {
+            std::env::remove_var(name);
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.upstream.servers, ["1.1.1.1", "8.8.8.8:53"]);
+        assert_eq!(config.api.api_key, None);
+        assert_eq!(config.dns.rate_limit, 20);
+        // Clé du fichier sans variable correspondante
+        assert_eq!(config.query_log.verbosity, LogVerbosity::Verbose);
+    }
+
+    #[test]
+    fn invalid_environment_values_are_errors() {
+        let _env = ENV.lock().unwrap();
+        std::env::set_var("RUSTHOLE_CONFIG", std::env::temp_dir().join("rusthole-absent.toml"));
+        assert!(Config::load().is_err());
+
+        let path = write_config("env-invalid", "");
+        std::env::set_var("RUSTHOLE_CONFIG", &path);
+        std::env::set_var("RUSTHOLE_CACHE_SIZE", "beaucoup");
+        let err = Config::load().unwrap_err();
+        std::env::remove_var("RUSTHOLE_CACHE_SIZE");
+        assert!(err.to_string().contains("RUSTHOLE_CACHE_SIZE"), "{}", err);
+
+        std::env::set_var("RUSTHOLE_CACHE_MIN_TTL", "600");
+        std::env::set_var("RUSTHOLE_CACHE_MAX_TTL", "60");
+        let result = Config::load();
+        for name in ["RUSTHOLE_CONFIG", "RUSTHOLE_CACHE_MIN_TTL", "RUSTHOLE_CACHE_MAX_TTL"] {
+            std::env::remove_var(name);
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
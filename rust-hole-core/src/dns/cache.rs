@@ -0,0 +1,532 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hickory_proto::op::{Message, ResponseCode};
+use hickory_proto::rr::{RData, Record, RecordType};
+use lru::LruCache;
+use metrics::gauge;
+use tracing::warn;
+
+use rust_hole_api::CacheEntryInfo;
+use rust_hole_db::{load_cache, save_cache, NewCacheEntry};
+
+pub const DEFAULT_CACHE_SIZE: usize = 10_000;
+pub const DEFAULT_SWEEP_INTERVAL_SECS: u64 = 60;
+pub const DEFAULT_MIN_TTL: u32 = 0;
+pub const DEFAULT_MAX_TTL: u32 = 86_400;
+pub const DEFAULT_NEGATIVE_TTL: u32 = 30;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CacheConfig {
+    pub max_entries: usize,
+    pub min_ttl: u32,
+    pub max_ttl: u32,
+    // Durée de vie des réponses NXDOMAIN / NODATA sans SOA
+    pub negative_ttl: u32,
+    pub cache_nxdomain: bool,
+    pub cache_nodata: bool,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: DEFAULT_CACHE_SIZE,
+            min_ttl: DEFAULT_MIN_TTL,
+            max_ttl: DEFAULT_MAX_TTL,
+            negative_ttl: DEFAULT_NEGATIVE_TTL,
+            cache_nxdomain: true,
+            cache_nodata: true,
+        }
+    }
+}
+
+// Chaque type d'enregistrement a sa propre entrée : une requête HTTPS ne doit
+// jamais recevoir la réponse A mise en cache (et inversement)
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub domain: String,
+    pub rtype: RecordType,
+    // Option ECS encodée, quand elle est transmise : la réponse dépend du sous-réseau
+    pub subnet: Option<Vec<u8>>,
+}
+
+impl CacheKey {
+    // Les variantes de casse d'un même nom partagent l'entrée (et la requête en attente)
+    pub fn new(domain: &str, rtype: RecordType, subnet: Option<Vec<u8>>) -> Self {
+        Self {
+            domain: domain.trim_end_matches('.').to_ascii_lowercase(),
+            rtype,
+            subnet,
+        }
+    }
+}
+
+struct CacheEntry {
+    msg: Message,
+    negative: bool,
+    // Les TTL servis sont diminués du temps passé en cache depuis cet instant
+    stored_at: Instant,
+    expires_at: Instant,
+}
+
+pub struct CachedMessage {
+    pub msg: Message,
+    // Réponse négative (NXDOMAIN / NODATA) venant de l'upstream
+    pub negative: bool,
+}
+
+// Cache LRU borné : au-delà de `max_entries`, l'entrée la moins récemment
+// utilisée est évincée
+pub struct DnsCache {
+    entries: Mutex<LruCache<CacheKey, CacheEntry>>,
+    min_ttl: u32,
+    max_ttl: u32,
+    negative_ttl: u32,
+    cache_nxdomain: bool,
+    cache_nodata: bool,
+}
+
+impl DnsCache {
+    pub fn new(config: CacheConfig) -> Self {
+        let capacity = NonZeroUsize::new(config.max_entries).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+            min_ttl: config.min_ttl,
+            max_ttl: config.max_ttl.max(config.min_ttl),
+            negative_ttl: config.negative_ttl,
+            cache_nxdomain: config.cache_nxdomain,
+            cache_nodata: config.cache_nodata,
+        }
+    }
+
+    // Durée de vie en cache, bornée par [min_ttl, max_ttl]
+    fn clamp_ttl(&self, ttl: u32) -> Duration {
+        Duration::from_secs(u64::from(ttl.clamp(self.min_ttl, self.max_ttl)))
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<CachedMessage> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if Instant::now() < entry.expires_at => Some(CachedMessage {
+                msg: aged(&entry.msg, entry.stored_at.elapsed()),
+                negative: entry.negative,
+            }),
+            Some(_) => {
+                entries.pop(key);
+                gauge!("rusthole_cache_entries").set(entries.len() as f64);
+                None
+            }
+            None => None,
+        }
+    }
+
+    // Met en cache une réponse upstream selon son code : les réponses positives
+    // vivent le plus petit TTL de leurs enregistrements, les négatives (si
+    // cache_nxdomain / cache_nodata le permettent) le TTL de leur SOA, et les
+    // erreurs (SERVFAIL, REFUSED...) ne sont pas gardées
+    pub fn store(&self, key: CacheKey, msg: &Message) {
+        let Some((ttl, negative)) = self.lifetime(msg) else {
+            return;
+        };
+
+        let now = Instant::now();
+        let entry = CacheEntry {
+            msg: msg.clone(),
+            negative,
+            stored_at: now,
+            expires_at: now + ttl,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        entries.put(key, entry);
+        gauge!("rusthole_cache_entries").set(entries.len() as f64);
+    }
+
+    // Durée en cache et caractère négatif de la réponse, `None` si elle ne se met pas en cache
+    fn lifetime(&self, msg: &Message) -> Option<(Duration, bool)> {
+        let answer_ttl = msg.answers().iter().map(|r| r.ttl()).min();
+
+        match (msg.response_code(), answer_ttl) {
+            (ResponseCode::NoError, Some(ttl)) => Some((self.clamp_ttl(ttl), false)),
+            (ResponseCode::NoError, None) if self.cache_nodata => Some((self.negative_lifetime(msg), true)),
+            (ResponseCode::NXDomain, _) if self.cache_nxdomain => Some((self.negative_lifetime(msg), true)),
+            _ => None,
+        }
+    }
+
+    // RFC 2308 : TTL du SOA de la section autorité, limité à son champ
+    // minimum, puis borné comme les autres. `negative_ttl` sans SOA
+    fn negative_lifetime(&self, msg: &Message) -> Duration {
+        let soa_ttl = msg.name_servers().iter().find_map(|r| match r.data() {
+            Some(RData::SOA(soa)) => Some(r.ttl().min(soa.minimum())),
+            _ => None,
+        });
+        match soa_ttl {
+            Some(ttl) => self.clamp_ttl(ttl),
+            None => Duration::from_secs(u64::from(self.negative_ttl)),
+        }
+    }
+
+    // Retire toutes les entrées, ou celles de `domain` (tous types et sous-réseaux)
+    pub fn flush(&self, domain: Option<&str>) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let evicted = match domain {
+            None => {
+                let count = entries.len();
+                entries.clear();
+                count
+            }
+            Some(domain) => {
+                let keys: Vec<CacheKey> = entries
+                    .iter()
+                    .filter(|(key, _)| key.domain == domain)
+                    .map(|(key, _)| key.clone())
+                    .collect();
+                for key in &keys {
+                    entries.pop(key);
+                }
+                keys.len()
+            }
+        };
+        gauge!("rusthole_cache_entries").set(entries.len() as f64);
+        evicted
+    }
+
+    // Copie du contenu pour GET /debug/cache, entrées expirées exclues
+    pub fn snapshot(&self) -> Vec<CacheEntryInfo> {
+        let now = Instant::now();
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| entry.expires_at > now)
+            .map(|(key, entry)| CacheEntryInfo {
+                domain: key.domain.clone(),
+                record_type: key.rtype.to_string(),
+                subnet: key.subnet.as_deref().and_then(format_subnet),
+                rcode: entry.msg.response_code().to_string(),
+                negative: entry.negative,
+                answers: entry.msg.answers().iter().map(format_record).collect(),
+                expires_in: (entry.expires_at - now).as_secs(),
+            })
+            .collect()
+    }
+
+    // Supprime toutes les entrées expirées et retourne leur nombre
+    pub fn purge_expired(&self) -> usize {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let expired: Vec<CacheKey> = entries
+            .iter()
+            .filter(|(_, entry)| entry.expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in &expired {
+            entries.pop(key);
+        }
+        gauge!("rusthole_cache_entries").set(entries.len() as f64);
+        expired.len()
+    }
+
+    // ---------- Persistance ----------
+    // `Instant` n'a de sens que pour ce processus : on stocke une expiration en
+    // heure Unix, convertie dans un sens à la sauvegarde et dans l'autre au chargement
+
+    pub async fn save(&self) -> anyhow::Result<usize> {
+        let now = Instant::now();
+        let unix_now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+
+        // Du moins au plus récemment utilisé, pour retrouver l'ordre LRU au chargement
+        let entries: Vec<NewCacheEntry> = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|(_, entry)| entry.expires_at > now)
+            .filter_map(|(key, entry)| {
+                Some(NewCacheEntry {
+                    domain: key.domain.clone(),
+                    record_type: u16::from(key.rtype),
+                    subnet: key.subnet.clone(),
+                    message: entry.msg.to_vec().ok()?,
+                    negative: entry.negative,
+                    expires_at: (unix_now + (entry.expires_at - now)).as_secs() as i64,
+                })
+            })
+            .collect();
+
+        let count = entries.len();
+        save_cache(entries).await?;
+        Ok(count)
+    }
+
+    pub async fn load(&self) -> anyhow::Result<usize> {
+        let unix_now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let rows = load_cache(unix_now).await?;
+        let now = Instant::now();
+
+        let mut entries = self.entries.lock().unwrap();
+        let mut count = 0;
+        for row in rows {
+            let msg = match Message::from_vec(&row.message) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    warn!(domain = %row.domain, error = %e, "Entrée de cache illisible ignorée");
+                    continue;
+                }
+            };
+            let key = CacheKey::new(&row.domain, RecordType::from(row.record_type as u16), row.subnet);
+            // Réponse que la configuration ne met plus en cache (cache_nxdomain, cache_nodata)
+            let Some((ttl, _)) = self.lifetime(&msg) else {
+                continue;
+            };
+            let remaining = Duration::from_secs((row.expires_at - unix_now).max(0) as u64);
+            let expires_at = now + remaining;
+            // Seule l'expiration est sauvegardée : l'âge se déduit de la durée en cache
+            let stored_at = expires_at.checked_sub(ttl).map_or(now, |stored_at| stored_at.min(now));
+            entries.put(
+                key,
+                CacheEntry {
+                    msg,
+                    negative: row.negative,
+                    stored_at,
+                    expires_at,
+                },
+            );
+            count += 1;
+        }
+        gauge!("rusthole_cache_entries").set(entries.len() as f64);
+        Ok(count)
+    }
+}
+
+// Copie de la réponse dont les TTL tiennent compte de son âge, comme le ferait
+// un résolveur : jamais moins de 1 s, un TTL à 0 interdirait au client de la garder
+fn aged(msg: &Message, age: Duration) -> Message {
+    let age = u32::try_from(age.as_secs()).unwrap_or(u32::MAX);
+    let mut msg = msg.clone();
+    let age_records = |records: &mut Vec<Record>| {
+        for record in records {
+            record.set_ttl(record.ttl().saturating_sub(age).max(1));
+        }
+    };
+    age_records(msg.answers_mut());
+    age_records(msg.name_servers_mut());
+    age_records(msg.additionals_mut());
+    msg
+}
+
+// "MX 10 mx1.example.net.". Chaque chaîne d'un TXT est entre guillemets comme
+// dans un fichier de zone : l'affichage de hickory les colle bout à bout
+fn format_record(record: &Record) -> String {
+    match record.data() {
+        Some(RData::TXT(txt)) => {
+            let strings: Vec<String> = txt
+                .iter()
+                .map(|s| format!("{:?}", String::from_utf8_lossy(s)))
+                .collect();
+            format!("{} {}", record.record_type(), strings.join(" "))
+        }
+        Some(data) => format!("{} {}", record.record_type(), data),
+        None => record.record_type().to_string(),
+    }
+}
+
+// Option ECS encodée (famille, préfixe source, préfixe retenu, adresse
+// tronquée) affichée en "192.168.1.0/24"
+fn format_subnet(option: &[u8]) -> Option<String> {
+    let (header, addr) = option.split_at_checked(4)?;
+    let family = u16::from_be_bytes([header[0], header[1]]);
+    let prefix = header[2];
+    let ip = match family {
+        1 => {
+            let mut octets = [0u8; 4];
+            octets.get_mut(..addr.len())?.copy_from_slice(addr);
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        2 => {
+            let mut octets = [0u8; 16];
+            octets.get_mut(..addr.len())?.copy_from_slice(addr);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some(format!("{}/{}", ip, prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::{A, SOA};
+    use hickory_proto::rr::Name;
+
+    fn key(domain: &str) -> CacheKey {
+        CacheKey::new(domain, RecordType::A, None)
+    }
+
+    fn positive(ttls: &[u32]) -> Message {
+        let mut msg = Message::new();
+        for (i, ttl) in ttls.iter().enumerate() {
+            let rdata = RData::A(A::new(10, 0, 0, i as u8 + 1));
+            msg.add_answer(Record::from_rdata(Name::from_ascii("ttl.test.").unwrap(), *ttl, rdata));
+        }
+        msg
+    }
+
+    #[test]
+    fn least_recently_used_entry_is_evicted() {
+        let cache = DnsCache::new(CacheConfig {
+            max_entries: 2,
+            ..Default::default()
+        });
+        cache.store(key("a.lru.test"), &positive(&[300]));
+        cache.store(key("b.lru.test"), &positive(&[300]));
+        assert!(cache.get(&key("a.lru.test")).is_some());
+        cache.store(key("c.lru.test"), &positive(&[300]));
+        assert!(cache.get(&key("b.lru.test")).is_none());
+        assert!(cache.get(&key("a.lru.test")).is_some());
+        assert!(cache.get(&key("c.lru.test")).is_some());
+    }
+
+    fn cache(min_ttl: u32, max_ttl: u32) -> DnsCache {
+        DnsCache::new(CacheConfig {
+            min_ttl,
+            max_ttl,
+            ..Default::default()
+        })
+    }
+
+    fn expires_in(cache: &DnsCache, key: &CacheKey) -> u64 {
+        let entries = cache.entries.lock().unwrap();
+        let remaining = entries.peek(key).unwrap().expires_at - Instant::now();
+        // Arrondi à la seconde supérieure : quelques microsecondes ont passé
+        remaining.as_secs() + 1
+    }
+
+    #[test]
+    fn positive_answers_live_their_smallest_ttl_within_bounds() {
+        let cache = cache(60, 3600);
+        cache.store(key("short.test"), &positive(&[300, 10]));
+        cache.store(key("mid.test"), &positive(&[300, 600]));
+        cache.store(key("long.test"), &positive(&[86_400]));
+        assert_eq!(expires_in(&cache, &key("short.test")), 60);
+        assert_eq!(expires_in(&cache, &key("mid.test")), 300);
+        assert_eq!(expires_in(&cache, &key("long.test")), 3600);
+    }
+
+    #[test]
+    fn errors_are_not_cached() {
+        let cache = cache(0, 3600);
+        for code in [ResponseCode::ServFail, ResponseCode::Refused] {
+            let mut msg = positive(&[300]);
+            msg.set_response_code(code);
+            cache.store(key("error.test"), &msg);
+        }
+        assert!(cache.get(&key("error.test")).is_none());
+    }
+
+    // Recule l'instant de mise en cache, comme si l'entrée y était depuis `age`
+    fn backdate(cache: &DnsCache, key: &CacheKey, age: Duration) {
+        let mut entries = cache.entries.lock().unwrap();
+        let entry = entries.peek_mut(key).unwrap();
+        entry.stored_at -= age;
+        entry.expires_at -= age;
+    }
+
+    fn ttls(msg: &Message) -> Vec<u32> {
+        msg.answers().iter().map(|r| r.ttl()).collect()
+    }
+
+    #[test]
+    fn served_ttls_shrink_with_time_in_cache() {
+        let cache = cache(0, 3600);
+        cache.store(key("aging.test"), &positive(&[300, 600]));
+        assert_eq!(ttls(&cache.get(&key("aging.test")).unwrap().msg), [300, 600]);
+
+        backdate(&cache, &key("aging.test"), Duration::from_secs(100));
+        assert_eq!(ttls(&cache.get(&key("aging.test")).unwrap().msg), [200, 500]);
+    }
+
+    #[test]
+    fn aged_ttls_never_reach_zero() {
+        // min_ttl garde l'entrée au-delà du TTL des enregistrements
+        let cache = cache(120, 3600);
+        cache.store(key("floor.test"), &positive(&[30]));
+        backdate(&cache, &key("floor.test"), Duration::from_secs(90));
+        assert_eq!(ttls(&cache.get(&key("floor.test")).unwrap().msg), [1]);
+    }
+
+    #[test]
+    fn expired_entries_are_dropped_on_read() {
+        let cache = cache(0, 3600);
+        cache.store(key("expired.test"), &positive(&[60]));
+        backdate(&cache, &key("expired.test"), Duration::from_secs(61));
+        assert!(cache.get(&key("expired.test")).is_none());
+        assert!(cache.entries.lock().unwrap().is_empty());
+    }
+
+    fn negative(code: ResponseCode, soa: Option<(u32, u32)>) -> Message {
+        let mut msg = Message::new();
+        msg.set_response_code(code);
+        if let Some((ttl, minimum)) = soa {
+            let soa = SOA::new(
+                Name::from_ascii("ns.test.").unwrap(),
+                Name::from_ascii("admin.test.").unwrap(),
+                1,
+                3600,
+                600,
+                86_400,
+                minimum,
+            );
+            msg.add_name_server(Record::from_rdata(Name::from_ascii("test.").unwrap(), ttl, RData::SOA(soa)));
+        }
+        msg
+    }
+
+    #[test]
+    fn negative_answers_live_the_soa_ttl_capped_by_its_minimum() {
+        let cache = cache(0, 3600);
+        cache.store(key("soa-ttl.test"), &negative(ResponseCode::NXDomain, Some((120, 900))));
+        cache.store(key("soa-min.test"), &negative(ResponseCode::NXDomain, Some((900, 60))));
+        cache.store(key("soa-nodata.test"), &negative(ResponseCode::NoError, Some((300, 300))));
+        assert_eq!(expires_in(&cache, &key("soa-ttl.test")), 120);
+        assert_eq!(expires_in(&cache, &key("soa-min.test")), 60);
+        assert_eq!(expires_in(&cache, &key("soa-nodata.test")), 300);
+        assert!(cache.get(&key("soa-nodata.test")).unwrap().negative);
+    }
+
+    #[test]
+    fn negative_answers_without_soa_use_negative_ttl() {
+        let cache = DnsCache::new(CacheConfig {
+            negative_ttl: 45,
+            ..Default::default()
+        });
+        cache.store(key("no-soa.test"), &negative(ResponseCode::NXDomain, None));
+        assert_eq!(expires_in(&cache, &key("no-soa.test")), 45);
+        assert!(cache.get(&key("no-soa.test")).unwrap().negative);
+    }
+
+    #[test]
+    fn negative_caching_can_be_disabled_per_kind() {
+        let cache = DnsCache::new(CacheConfig {
+            cache_nxdomain: false,
+            ..Default::default()
+        });
+        cache.store(key("nx.test"), &negative(ResponseCode::NXDomain, Some((300, 300))));
+        cache.store(key("nodata.test"), &negative(ResponseCode::NoError, Some((300, 300))));
+        assert!(cache.get(&key("nx.test")).is_none());
+        assert!(cache.get(&key("nodata.test")).is_some());
+
+        let cache = DnsCache::new(CacheConfig {
+            cache_nodata: false,
+            ..Default::default()
+        });
+        cache.store(key("nx.test"), &negative(ResponseCode::NXDomain, Some((300, 300))));
+        cache.store(key("nodata.test"), &negative(ResponseCode::NoError, Some((300, 300))));
+        assert!(cache.get(&key("nx.test")).is_some());
+        assert!(cache.get(&key("nodata.test")).is_none());
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::path::Path;
+
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use tracing::{info, warn};
+
+use rust_hole_db::normalize_domain;
+
+const HOSTS_TTL: u32 = 300;
+
+// ================= Hosts file =================
+// Surcharges locales au format /etc/hosts (`192.168.1.10 nas nas.lan`),
+// relues à chaque modification du fichier
+#[derive(Default)]
+pub struct HostsFile {
+    records: HashMap<(String, RecordType), Vec<Record>>,
+}
+
+impl HostsFile {
+    // Un fichier absent donne une table vide : il peut être créé plus tard
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        match tokio::fs::read_to_string(path).await {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!(path = %path.display(), "Fichier hosts absent, ignoré");
+                Ok(Self::default())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn parse(content: &str) -> Self {
+        let mut hosts = Self::default();
+
+        for (number, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let mut tokens = line.split_whitespace();
+            let Some(addr) = tokens.next() else { continue };
+
+            let rdata = match addr.parse::<IpAddr>() {
+                Ok(IpAddr::V4(ip)) => RData::A(A(ip)),
+                Ok(IpAddr::V6(ip)) => RData::AAAA(AAAA(ip)),
+                Err(_) => {
+                    warn!(line = number + 1, addr, "Adresse invalide dans le fichier hosts");
+                    continue;
+                }
+            };
+            for token in tokens {
+                let Ok(domain) = normalize_domain(token) else {
+                    warn!(line = number + 1, name = token, "Nom invalide dans le fichier hosts");
+                    continue;
+                };
+                let Ok(name) = Name::from_ascii(&domain) else { continue };
+                hosts
+                    .records
+                    .entry((domain, rdata.record_type()))
+                    .or_default()
+                    .push(Record::from_rdata(name, HOSTS_TTL, rdata.clone()));
+            }
+        }
+        hosts
+    }
+
+    pub fn count(&self) -> usize {
+        self.records.values().map(Vec::len).sum()
+    }
+
+    // Seuls les types présents dans le fichier (A, AAAA) sont servis,
+    // les autres suivent le chemin habituel
+    pub fn lookup(&self, name: &str, rtype: RecordType) -> Option<Vec<Record>> {
+        self.records.get(&(name.to_string(), rtype)).cloned()
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use hickory_proto::rr::rdata::{A, AAAA, CNAME};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use tracing::warn;
+
+use rust_hole_db::get_all_local_records;
+use rust_hole_db::models::local_records::Model as LocalRecordModel;
+
+// Évite de boucler sur des CNAME qui se référencent entre eux
+const MAX_CNAME_CHAIN: usize = 8;
+
+// ================= Local records =================
+// Enregistrements servis directement, sans passer par l'upstream
+#[derive(Default)]
+pub struct LocalRecords {
+    records: HashMap<String, Vec<Record>>,
+}
+
+fn to_record(model: &LocalRecordModel) -> anyhow::Result<Record> {
+    let name = Name::from_ascii(&model.domain)?;
+    let rdata = match model.record_type.to_ascii_uppercase().as_str() {
+        "A" => RData::A(A(model.value.parse::<Ipv4Addr>()?)),
+        "AAAA" => RData::AAAA(AAAA(model.value.parse::<Ipv6Addr>()?)),
+        "CNAME" => RData::CNAME(CNAME(Name::from_ascii(&model.value)?)),
+        other => anyhow::bail!("Type d'enregistrement non supporté: {}", other),
+    };
+    Ok(Record::from_rdata(name, u32::try_from(model.ttl)?, rdata))
+}
+
+impl LocalRecords {
+    pub async fn load() -> anyhow::Result<Self> {
+        let mut local = Self::default();
+
+        for model in get_all_local_records().await? {
+            match to_record(&model) {
+                Ok(record) => local
+                    .records
+                    .entry(model.domain.trim_end_matches('.').to_ascii_lowercase())
+                    .or_default()
+                    .push(record),
+                Err(e) => warn!(id = model.id, domain = %model.domain, error = %e, "Enregistrement local invalide ignoré"),
+            }
+        }
+        Ok(local)
+    }
+
+    pub fn count(&self) -> usize {
+        self.records.values().map(Vec::len).sum()
+    }
+
+    // Réponses pour `name`, ou `None` si le nom n'est pas géré localement.
+    // Une liste vide correspond à un NODATA (le nom existe, pas ce type).
+    pub fn lookup(&self, name: &str, rtype: RecordType) -> Option<Vec<Record>> {
+        let mut current = name.to_ascii_lowercase();
+        let mut answers = Vec::new();
+
+        for _ in 0..MAX_CNAME_CHAIN {
+            let Some(records) = self.records.get(&current) else {
+                // La cible d'un CNAME sort du local : le client la résoudra
+                return (!answers.is_empty()).then_some(answers);
+            };
+
+            let matching: Vec<Record> = records
+                .iter()
+                .filter(|r| r.record_type() == rtype)
+                .cloned()
+                .collect();
+            if !matching.is_empty() {
+                answers.extend(matching);
+                return Some(answers);
+            }
+
+            let Some(cname) = records.iter().find(|r| r.record_type() == RecordType::CNAME) else {
+                return Some(answers);
+            };
+            if let Some(RData::CNAME(target)) = cname.data() {
+                current = target.0.to_ascii().trim_end_matches('.').to_ascii_lowercase();
+            }
+            answers.push(cname.clone());
+        }
+        Some(answers)
+    }
+}
@@ -1 +1,13 @@
+pub mod cache;
+pub mod hosts_file;
+pub mod local;
+pub mod pending;
+pub mod query_log;
+pub mod rate_limit;
+pub mod reverse;
 pub mod server;
+pub mod stats;
+#[cfg(test)]
+pub mod testing;
+pub mod trie;
+pub mod upstream;
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use hickory_proto::op::{Message, ResponseCode};
+use tokio::sync::oneshot;
+
+use super::cache::CacheKey;
+
+// Réponse complète de l'upstream (NXDOMAIN compris), ou le code renvoyé
+// au client quand l'upstream n'a pas répondu
+pub type Outcome = Result<Message, ResponseCode>;
+
+// ================= Pending queries =================
+// Requêtes identiques arrivées pendant un forward : une seule part vers
+// l'upstream, les autres attendent sa réponse
+struct PendingQuery {
+    waiters: Vec<oneshot::Sender<Outcome>>,
+}
+
+#[derive(Default)]
+pub struct PendingQueries {
+    pending: Mutex<HashMap<CacheKey, PendingQuery>>,
+}
+
+pub enum Role<'a> {
+    Leader(Leader<'a>),
+    Waiter(oneshot::Receiver<Outcome>),
+}
+
+impl PendingQueries {
+    pub fn join(&self, key: &CacheKey) -> Role<'_> {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(query) = pending.get_mut(key) {
+            let (tx, rx) = oneshot::channel();
+            query.waiters.push(tx);
+            return Role::Waiter(rx);
+        }
+        pending.insert(key.clone(), PendingQuery { waiters: Vec::new() });
+        Role::Leader(Leader {
+            pending: self,
+            key: Some(key.clone()),
+        })
+    }
+
+    fn complete(&self, key: &CacheKey, outcome: &Outcome) {
+        let Some(query) = self.pending.lock().unwrap().remove(key) else {
+            return;
+        };
+        for waiter in query.waiters {
+            // Le client a pu abandonner entre-temps
+            let _ = waiter.send(outcome.clone());
+        }
+    }
+}
+
+// Réveille les requêtes en attente. Si le leader disparaît sans réponse,
+// elles reçoivent un SERVFAIL plutôt que d'attendre indéfiniment
+pub struct Leader<'a> {
+    pending: &'a PendingQueries,
+    key: Option<CacheKey>,
+}
+
+impl Leader<'_> {
+    pub fn finish(mut self, outcome: &Outcome) {
+        if let Some(key) = self.key.take() {
+            self.pending.complete(&key, outcome);
+        }
+    }
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.pending.complete(&key, &Err(ResponseCode::ServFail));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::RecordType;
+
+    fn key(domain: &str) -> CacheKey {
+        CacheKey::new(domain, RecordType::A, None)
+    }
+
+    #[tokio::test]
+    async fn waiters_get_the_leader_outcome() {
+        let pending = PendingQueries::default();
+        let Role::Leader(leader) = pending.join(&key("nx.pending.test")) else {
+            panic!("la première requête mène");
+        };
+        let waiters: Vec<_> = (0..3)
+            .map(|_| match pending.join(&key("NX.pending.test.")) {
+                Role::Waiter(rx) => rx,
+                Role::Leader(_) => panic!("requête identique déjà en vol"),
+            })
+            .collect();
+
+        let mut nxdomain = Message::new();
+        nxdomain.set_response_code(ResponseCode::NXDomain);
+        leader.finish(&Ok(nxdomain));
+        for rx in waiters {
+            let resp = rx.await.unwrap().unwrap();
+            assert_eq!(resp.response_code(), ResponseCode::NXDomain);
+        }
+        // Entrée retirée : la requête suivante repart vers l'upstream
+        assert!(matches!(pending.join(&key("nx.pending.test")), Role::Leader(_)));
+    }
+
+    #[tokio::test]
+    async fn dropped_leader_releases_waiters_with_servfail() {
+        let pending = PendingQueries::default();
+        let leader = pending.join(&key("drop.pending.test"));
+        let Role::Waiter(rx) = pending.join(&key("drop.pending.test")) else {
+            panic!("requête identique déjà en vol");
+        };
+        drop(leader);
+        assert_eq!(rx.await.unwrap().unwrap_err(), ResponseCode::ServFail);
+    }
+
+    #[test]
+    fn different_keys_do_not_wait_on_each_other() {
+        let pending = PendingQueries::default();
+        let _a = pending.join(&key("a.pending.test"));
+        assert!(matches!(pending.join(&key("b.pending.test")), Role::Leader(_)));
+        assert!(matches!(
+            pending.join(&CacheKey::new("a.pending.test", RecordType::AAAA, None)),
+            Role::Leader(_)
+        ));
+    }
+}
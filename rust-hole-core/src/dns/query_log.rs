@@ -0,0 +1,237 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hickory_proto::rr::RecordType;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use rust_hole_api::QueryEvent;
+use rust_hole_db::{log_queries, NewQueryLog, QuerySource, QueryStatus};
+
+const QUEUE_SIZE: usize = 10_000;
+const MAX_BATCH: usize = 500;
+// Un abonné plus lent que ça perd les événements les plus anciens
+const EVENTS_CAPACITY: usize = 1024;
+// Refus du rate limit journalisés par seconde : un client qui inonde le
+// serveur ne remplit pas le journal. Les suivants sont seulement comptés
+const RATE_LIMITED_PER_SEC: u32 = 10;
+
+// Lignes de log par requête (RUSTHOLE_QUERY_LOG), indépendantes du journal
+// en base : off n'en écrit aucune, summary seulement les requêtes bloquées ou
+// en échec (les autres en debug), verbose toutes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogVerbosity {
+    Off,
+    #[default]
+    Summary,
+    Verbose,
+}
+
+impl FromStr for LogVerbosity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "summary" => Ok(Self::Summary),
+            "verbose" => Ok(Self::Verbose),
+            other => Err(anyhow::anyhow!("Niveau de journal inconnu: {}", other)),
+        }
+    }
+}
+
+// Les entrées passent par un canal borné : l'écriture en base ne ralentit
+// jamais la réponse DNS, quitte à perdre des entrées si la file est pleine
+pub struct QueryLogger {
+    tx: mpsc::Sender<NewQueryLog>,
+    // Flux en direct (/ws/querylog), sans historique
+    events: broadcast::Sender<QueryEvent>,
+    verbosity: LogVerbosity,
+    rate_limited: Mutex<RateLimitedWindow>,
+}
+
+// Refus du rate limit journalisés et écartés pendant la seconde en cours
+#[derive(Default)]
+struct RateLimitedWindow {
+    second: i64,
+    logged: u32,
+    dropped: u64,
+}
+
+// Tâche d'écriture, à vider explicitement à l'arrêt
+pub struct QueryLogWriter {
+    shutdown: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl QueryLogWriter {
+    // Écrit les entrées encore en file puis arrête la tâche
+    pub async fn flush(self) {
+        let _ = self.shutdown.send(());
+        if let Err(e) = self.handle.await {
+            error!(error = %e, "Erreur à l'arrêt du journal des requêtes");
+        }
+    }
+}
+
+impl QueryLogger {
+    pub fn start(verbosity: LogVerbosity) -> (Self, QueryLogWriter) {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let handle = tokio::spawn(run_writer(rx, shutdown_rx));
+        let (events, _) = broadcast::channel(EVENTS_CAPACITY);
+        let logger = Self {
+            tx,
+            events,
+            verbosity,
+            rate_limited: Mutex::default(),
+        };
+        (logger, QueryLogWriter { shutdown, handle })
+    }
+
+    pub fn verbosity(&self) -> LogVerbosity {
+        self.verbosity
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<QueryEvent> {
+        self.events.subscribe()
+    }
+
+    // `false` pour un refus du rate limit au-delà du quota de la seconde : ni
+    // ligne de log ni entrée au journal. Le nombre écarté est résumé une fois
+    // la seconde écoulée
+    pub fn sample(&self, source: QuerySource) -> bool {
+        if source != QuerySource::RateLimited {
+            return true;
+        }
+        let now = unix_now();
+        let mut window = self.rate_limited.lock().unwrap();
+        if window.second != now {
+            if window.dropped > 0 {
+                info!(dropped = window.dropped, "Requêtes refusées par le rate limit non journalisées");
+            }
+            *window = RateLimitedWindow { second: now, ..Default::default() };
+        }
+        if window.logged < RATE_LIMITED_PER_SEC {
+            window.logged += 1;
+            true
+        } else {
+            window.dropped += 1;
+            false
+        }
+    }
+
+    pub fn log(
+        &self,
+        client: IpAddr,
+        domain: &str,
+        rtype: RecordType,
+        status: QueryStatus,
+        source: QuerySource,
+    ) {
+        let timestamp = unix_now();
+
+        // Erreur uniquement quand personne n'écoute
+        let _ = self.events.send(QueryEvent {
+            timestamp,
+            client: client.to_string(),
+            domain: domain.to_string(),
+            query_type: rtype.to_string(),
+            outcome: status.as_str().to_string(),
+            source: source.as_str().to_string(),
+        });
+
+        let entry = NewQueryLog {
+            timestamp,
+            client_ip: client.to_string(),
+            domain: domain.to_string(),
+            query_type: rtype.to_string(),
+            status,
+            source,
+        };
+        if self.tx.try_send(entry).is_err() {
+            warn!(domain, "File du journal pleine, entrée ignorée");
+        }
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+async fn run_writer(mut rx: mpsc::Receiver<NewQueryLog>, mut shutdown: oneshot::Receiver<()>) {
+    loop {
+        let first = tokio::select! {
+            entry = rx.recv() => match entry {
+                Some(entry) => entry,
+                None => return,
+            },
+            _ = &mut shutdown => break,
+        };
+        write_batch(&mut rx, vec![first]).await;
+    }
+
+    // Arrêt : on vide la file avant de rendre la main
+    let mut pending = Vec::new();
+    while let Ok(entry) = rx.try_recv() {
+        pending.push(entry);
+    }
+    let count = pending.len();
+    while !pending.is_empty() {
+        let rest = pending.split_off(pending.len().min(MAX_BATCH));
+        if let Err(e) = log_queries(pending).await {
+            error!(error = %e, "Erreur d'écriture du journal des requêtes");
+        }
+        pending = rest;
+    }
+    info!(pending = count, "Journal des requêtes vidé");
+}
+
+async fn write_batch(rx: &mut mpsc::Receiver<NewQueryLog>, mut batch: Vec<NewQueryLog>) {
+    while batch.len() < MAX_BATCH {
+        match rx.try_recv() {
+            Ok(entry) => batch.push(entry),
+            Err(_) => break,
+        }
+    }
+
+    if let Err(e) = log_queries(batch).await {
+        error!(error = %e, "Erreur d'écriture du journal des requêtes");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rate_limited_entries_are_sampled() {
+        let (logger, _writer) = QueryLogger::start(LogVerbosity::Off);
+        let kept = (0..50).filter(|_| logger.sample(QuerySource::RateLimited)).count();
+        // Les 50 appels tiennent presque toujours dans la même seconde
+        assert!(kept >= RATE_LIMITED_PER_SEC as usize);
+        assert!(kept <= 2 * RATE_LIMITED_PER_SEC as usize);
+        assert!((0..50).all(|_| logger.sample(QuerySource::Forwarded)));
+    }
+
+    #[tokio::test]
+    async fn events_carry_status_and_source() {
+        let (logger, _writer) = QueryLogger::start(LogVerbosity::Off);
+        let mut events = logger.subscribe();
+        let client = IpAddr::from([192, 168, 1, 10]);
+        logger.log(client, "example.com", RecordType::AAAA, QueryStatus::Cached, QuerySource::Cache);
+
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.client, "192.168.1.10");
+        assert_eq!(event.domain, "example.com");
+        assert_eq!(event.query_type, "AAAA");
+        assert_eq!(event.outcome, "cached");
+        assert_eq!(event.source, "cache");
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Requêtes par seconde et par client, 0 pour désactiver
+pub const DEFAULT_RATE_LIMIT_QPS: u32 = 100;
+// Un client silencieux depuis ce délai a de toute façon un seau plein
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// ================= Token bucket =================
+struct Bucket {
+    tokens: f64,
+    last_seen: Instant,
+}
+
+// Un seau par IP, rempli à `qps` jetons par seconde et plafonné à `qps`
+// (soit une seconde de rafale)
+pub struct RateLimiter {
+    qps: u32,
+    buckets: Arc<Mutex<HashMap<IpAddr, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(qps: u32) -> Self {
+        Self {
+            qps,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Consomme un jeton, `false` si le client a dépassé sa limite
+    pub fn check(&self, client: IpAddr) -> bool {
+        if self.qps == 0 {
+            return true;
+        }
+        let capacity = f64::from(self.qps);
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            last_seen: now,
+        });
+        let elapsed = now.duration_since(bucket.last_seen).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * capacity).min(capacity);
+        bucket.last_seen = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    // Retire les clients inactifs, sinon la table grossit à chaque nouvelle IP
+    pub fn purge_idle(&self) -> usize {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let before = buckets.len();
+        buckets.retain(|_, b| now.duration_since(b.last_seen) < IDLE_TIMEOUT);
+        before - buckets.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 50));
+
+    // Recule le dernier passage du client, comme s'il était resté silencieux `idle`
+    fn idle(limiter: &RateLimiter, client: IpAddr, idle: Duration) {
+        limiter.buckets.lock().unwrap().get_mut(&client).unwrap().last_seen -= idle;
+    }
+
+    #[test]
+    fn allows_a_burst_of_qps_then_refuses() {
+        let limiter = RateLimiter::new(5);
+        assert!((0..5).all(|_| limiter.check(CLIENT)));
+        assert!(!limiter.check(CLIENT));
+        // Les autres clients ont leur propre seau
+        assert!(limiter.check("10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn bucket_refills_at_qps_and_caps_at_one_second() {
+        let limiter = RateLimiter::new(10);
+        assert!((0..10).all(|_| limiter.check(CLIENT)));
+        idle(&limiter, CLIENT, Duration::from_millis(500));
+        assert_eq!((0..10).filter(|_| limiter.check(CLIENT)).count(), 5);
+
+        idle(&limiter, CLIENT, Duration::from_secs(30));
+        assert_eq!((0..20).filter(|_| limiter.check(CLIENT)).count(), 10);
+    }
+
+    #[test]
+    fn zero_qps_disables_the_limit() {
+        let limiter = RateLimiter::new(0);
+        assert!((0..1000).all(|_| limiter.check(CLIENT)));
+        assert!(limiter.buckets.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn purge_drops_only_idle_clients() {
+        let limiter = RateLimiter::new(10);
+        let other: IpAddr = "10.0.0.1".parse().unwrap();
+        limiter.check(CLIENT);
+        limiter.check(other);
+        idle(&limiter, CLIENT, IDLE_TIMEOUT);
+        assert_eq!(limiter.purge_idle(), 1);
+        assert!(limiter.buckets.lock().unwrap().contains_key(&other));
+    }
+}
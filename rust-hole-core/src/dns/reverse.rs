@@ -0,0 +1,126 @@
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hickory_proto::rr::{Name, RData, RecordType};
+use lru::LruCache;
+use tracing::debug;
+
+use super::upstream::{self, ConditionalForwarders, UpstreamConfig};
+
+const PTR_TTL: Duration = Duration::from_secs(3600);
+// Redemandé plus tôt, au cas où le PTR serait ajouté entre-temps. Les
+// échecs de l'upstream ne sont pas mis en cache
+const NO_PTR_TTL: Duration = Duration::from_secs(600);
+// Un scan du réseau ou des clients IPv6 éphémères ne doivent pas faire
+// grossir le cache sans limite
+const MAX_ENTRIES: NonZeroUsize = NonZeroUsize::new(4096).unwrap();
+
+// ================= Reverse lookups =================
+// Noms des clients pour le dashboard, mis en cache pour ne pas interroger
+// l'upstream à chaque affichage
+pub struct ReverseResolver {
+    cache: Mutex<LruCache<IpAddr, (Option<String>, Instant)>>,
+}
+
+impl Default for ReverseResolver {
+    fn default() -> Self {
+        Self::with_capacity(MAX_ENTRIES)
+    }
+}
+
+impl ReverseResolver {
+    fn with_capacity(capacity: NonZeroUsize) -> Self {
+        Self {
+            cache: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    // `None` si l'IP n'a pas d'enregistrement PTR ou si l'upstream ne répond pas
+    pub async fn lookup(
+        &self,
+        ip: IpAddr,
+        upstream: &UpstreamConfig,
+        conditional: &ConditionalForwarders,
+    ) -> Option<String> {
+        if let Some(hostname) = self.cached(ip) {
+            return hostname;
+        }
+
+        let ptr_name = Name::from(ip);
+        // Les zones inverses du réseau local passent souvent par un transfert conditionnel
+        let ptr_ascii = ptr_name.to_ascii();
+        let upstream = conditional
+            .select(ptr_ascii.trim_end_matches('.'))
+            .unwrap_or(upstream);
+
+        let (hostname, ttl) = match resolve_ptr(ptr_name, upstream).await {
+            Ok(Some(hostname)) => (Some(hostname), PTR_TTL),
+            Ok(None) => (None, NO_PTR_TTL),
+            Err(e) => {
+                debug!(%ip, error = format!("{:#}", e), "Résolution inverse impossible");
+                return None;
+            }
+        };
+        self.store(ip, hostname.clone(), ttl);
+        hostname
+    }
+
+    // `Some(None)` : absence de PTR encore en cache. Une entrée expirée est retirée
+    fn cached(&self, ip: IpAddr) -> Option<Option<String>> {
+        let mut cache = self.cache.lock().unwrap();
+        match cache.get(&ip) {
+            Some((hostname, expires)) if *expires > Instant::now() => Some(hostname.clone()),
+            Some(_) => {
+                cache.pop(&ip);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn store(&self, ip: IpAddr, hostname: Option<String>, ttl: Duration) {
+        self.cache.lock().unwrap().put(ip, (hostname, Instant::now() + ttl));
+    }
+}
+
+async fn resolve_ptr(name: Name, upstream: &UpstreamConfig) -> anyhow::Result<Option<String>> {
+    let resp = upstream::query(upstream, name, RecordType::PTR).await?;
+    Ok(resp.answers().iter().find_map(|r| match r.data() {
+        Some(RData::PTR(ptr)) => Some(ptr.0.to_utf8().trim_end_matches('.').to_string()),
+        _ => None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_is_bounded() {
+        let resolver = ReverseResolver::with_capacity(NonZeroUsize::new(2).unwrap());
+        for last in 1..=3u8 {
+            resolver.store(IpAddr::from([192, 168, 1, last]), Some(format!("host{last}")), PTR_TTL);
+        }
+        // La plus ancienne entrée est évincée
+        assert_eq!(resolver.cached(IpAddr::from([192, 168, 1, 1])), None);
+        assert_eq!(
+            resolver.cached(IpAddr::from([192, 168, 1, 3])),
+            Some(Some("host3".to_string()))
+        );
+        assert_eq!(resolver.cache.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let resolver = ReverseResolver::default();
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        resolver.store(ip, None, Duration::ZERO);
+        assert_eq!(resolver.cached(ip), None);
+        assert!(resolver.cache.lock().unwrap().is_empty());
+
+        resolver.store(ip, None, NO_PTR_TTL);
+        assert_eq!(resolver.cached(ip), Some(None));
+    }
+}
@@ -1,110 +1,1447 @@
-use std::net::SocketAddr;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use dashmap::DashMap;
-use tokio::net::UdpSocket;
+use async_trait::async_trait;
+use metrics::{counter, gauge, histogram};
+use ipnet::IpNet;
+use regex::Regex;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::timeout;
+use notify::{EventKind, RecursiveMode, Watcher};
+use tracing::{debug, error, info, warn};
 
-use hickory_proto::op::{Message, MessageType, OpCode, ResponseCode};
-use hickory_proto::rr::RecordType;
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use hickory_proto::rr::rdata::{A, AAAA, HINFO, SOA, TXT};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
 
-use rust_hole_db::get_all_blocked_domains;
+use super::cache::{CacheConfig, CacheKey, DnsCache};
+use super::hosts_file::HostsFile;
+use super::local::LocalRecords;
+use super::pending::{PendingQueries, Role};
+use super::query_log::{LogVerbosity, QueryLogger};
+use super::rate_limit::RateLimiter;
+use super::reverse::ReverseResolver;
+use super::stats::Stats;
+use super::trie::DomainTrie;
+use super::upstream::{self, ConditionalForwarders, UpstreamConfig, PROBE_DOMAIN};
+use rust_hole_api::{BlocklistInfo, CacheEntryInfo, DnsHandle, DomainCheck, QueryEvent, StatsSnapshot};
+use rust_hole_db::{
+    delete_blocked_domain, find_active_blocked_domain, get_active_blocked_domains,
+    get_all_allowed_domains, get_all_blocklist_sources, get_all_clients,
+    get_all_group_blocked_domains, get_all_regex_rules, insert_blocked_domain, QuerySource, QueryStatus,
+};
+use rust_hole_db::models::blocked_domains::Model as BlockedDomainModel;
+use rust_hole_db::models::blocklist_sources::Model as BlocklistSourceModel;
 
-// ================= Cache =================
-struct CacheEntry {
-    msg: Message,
-    expires_at: Instant,
+// ================= Blocking mode =================
+pub const DEFAULT_BLOCKED_TTL: u32 = 60;
+// Serveur et contact annoncés dans le SOA des réponses bloquées
+pub const DEFAULT_SOA_MNAME: &str = "rust.hole.";
+pub const DEFAULT_SOA_RNAME: &str = "hostmaster.rust.hole.";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlockingMode {
+    #[default]
+    NxDomain,
+    // 0.0.0.0 pour A, :: pour AAAA
+    NullIp,
+    Refused,
 }
 
-// ================= DNS Server =================
-pub async fn run_dns() -> anyhow::Result<()> {
-    let socket = UdpSocket::bind("127.0.0.2:53").await?;
-    let upstream: SocketAddr = "8.8.8.8:53".parse()?;
+impl FromStr for BlockingMode {
+    type Err = anyhow::Error;
 
-    // Upstream socket réutilisé
-    let upstream_socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nxdomain" => Ok(Self::NxDomain),
+            "nullip" | "null" => Ok(Self::NullIp),
+            "refused" => Ok(Self::Refused),
+            other => Err(anyhow::anyhow!("Mode de blocage inconnu: {}", other)),
+        }
+    }
+}
 
-    let blocked: Vec<String> = get_all_blocked_domains()
-        .await?
-        .into_iter()
-        .map(|d| d.domain)
-        .collect();
+pub struct BlockingConfig {
+    pub mode: BlockingMode,
+    // TTL des réponses bloquées, y compris le cache négatif côté client (SOA)
+    pub ttl: u32,
+    pub soa_mname: Name,
+    pub soa_rname: Name,
+    // Requêtes TXT bloquées : un TXT qui nomme la source plutôt que la
+    // réponse du mode de blocage, pour le diagnostic (dig TXT)
+    pub txt_reason: bool,
+}
 
-    let blocked = Arc::new(blocked);
-    let cache = Arc::new(DashMap::<(String, RecordType), CacheEntry>::new());
+// ================= Client access =================
+// Contrôles par adresse du client, appliqués avant tout traitement
+pub struct AccessConfig {
+    // Requêtes par seconde et par client, 0 pour désactiver
+    pub rate_limit_qps: u32,
+    // Sous-réseaux autorisés à utiliser le résolveur, tous si la liste est vide
+    pub allowed_clients: Vec<IpNet>,
+    // Transmettre les requêtes ANY à l'upstream plutôt que d'y répondre localement
+    pub forward_any: bool,
+}
 
-    let mut buf = [0u8; 4096];
+// Réponse minimale aux requêtes ANY (RFC 8482), qui servent surtout à
+// l'amplification : une réponse courte au lieu de tous les enregistrements
+const ANY_HINFO_CPU: &str = "RFC8482";
+const ANY_HINFO_TTL: u32 = 3600;
 
-    loop {
-        let (len, peer) = socket.recv_from(&mut buf).await?;
-        let req_bytes = &buf[..len];
+// ================= Client groups =================
+// Les clients d'un groupe ont, en plus de la blocklist par défaut, les
+// domaines propres à leur groupe
+#[derive(Default)]
+struct ClientGroups {
+    clients: HashMap<IpAddr, i64>,
+    blocked: HashMap<i64, DomainTrie>,
+}
+
+impl ClientGroups {
+    async fn load() -> anyhow::Result<Self> {
+        let mut groups = Self::default();
+
+        for client in get_all_clients().await? {
+            match client.ip.parse::<IpAddr>() {
+                Ok(ip) => {
+                    groups.clients.insert(ip, client.group_id);
+                }
+                Err(_) => warn!(ip = %client.ip, id = client.id, "Adresse client invalide ignorée"),
+            }
+        }
+        for entry in get_all_group_blocked_domains().await? {
+            groups.blocked.entry(entry.group_id).or_default().insert(&entry.domain);
+        }
+        Ok(groups)
+    }
+
+    // Groupe du client et entrée de sa blocklist qui couvre `name`
+    fn find<'a>(&self, client: IpAddr, name: &'a str) -> Option<(i64, &'a str)> {
+        let group = *self.clients.get(&client)?;
+        self.blocked.get(&group)?.find(name).map(|entry| (group, entry))
+    }
+}
+
+// Catégorie de la source si elle en a une, sinon l'hôte de son URL
+fn source_name(source: &BlocklistSourceModel) -> String {
+    if let Some(category) = &source.category {
+        return category.clone();
+    }
+    let url = source.url.split_once("://").map_or(source.url.as_str(), |(_, rest)| rest);
+    let host = url.split(['/', '?', '#']).next().unwrap_or(url);
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    host.to_string()
+}
+
+// ================= Blocker =================
+pub struct DnsBlocker {
+    blocked: RwLock<DomainTrie>,
+    // Nom des sources de la blocklist, pour expliquer un blocage
+    source_names: RwLock<HashMap<i64, String>>,
+    allowed: RwLock<DomainTrie>,
+    // Motifs compilés une fois par rechargement
+    regex_rules: RwLock<Vec<Regex>>,
+    groups: RwLock<ClientGroups>,
+    local_records: RwLock<LocalRecords>,
+    hosts_file: RwLock<HostsFile>,
+    cache: DnsCache,
+    pending: PendingQueries,
+    blocking_mode: BlockingMode,
+    blocked_ttl: u32,
+    txt_reason: bool,
+    soa_mname: Name,
+    soa_rname: Name,
+    upstream: UpstreamConfig,
+    conditional: ConditionalForwarders,
+    // Transmet l'option EDNS Client Subnet à l'upstream au lieu de la retirer
+    ecs_passthrough: bool,
+    query_log: QueryLogger,
+    allowed_clients: Vec<IpNet>,
+    forward_any: bool,
+    rate_limiter: RateLimiter,
+    reverse: ReverseResolver,
+    stats: Stats,
+    // Dernier rechargement réussi de reload_blocklist
+    last_reload: Mutex<Option<SystemTime>>,
+    // Blocage suspendu jusqu'à cet instant (POST /disable), en millisecondes
+    // depuis `started`, 0 sinon. Lu à chaque requête : un atomique plutôt
+    // qu'un Mutex que toutes les requêtes se disputeraient
+    disabled_until: AtomicU64,
+    started: Instant,
+}
+
+impl DnsBlocker {
+    pub async fn new(
+        blocking: BlockingConfig,
+        upstream: UpstreamConfig,
+        conditional: ConditionalForwarders,
+        ecs_passthrough: bool,
+        query_log: QueryLogger,
+        cache: CacheConfig,
+        access: AccessConfig,
+    ) -> anyhow::Result<Self> {
+        let blocker = Self {
+            blocked: RwLock::new(DomainTrie::default()),
+            source_names: RwLock::new(HashMap::new()),
+            allowed: RwLock::new(DomainTrie::default()),
+            regex_rules: RwLock::new(Vec::new()),
+            groups: RwLock::new(ClientGroups::default()),
+            local_records: RwLock::new(LocalRecords::default()),
+            hosts_file: RwLock::new(HostsFile::default()),
+            cache: DnsCache::new(cache),
+            pending: PendingQueries::default(),
+            blocking_mode: blocking.mode,
+            blocked_ttl: blocking.ttl,
+            txt_reason: blocking.txt_reason,
+            soa_mname: blocking.soa_mname,
+            soa_rname: blocking.soa_rname,
+            upstream,
+            conditional,
+            ecs_passthrough,
+            query_log,
+            allowed_clients: access.allowed_clients,
+            forward_any: access.forward_any,
+            rate_limiter: RateLimiter::new(access.rate_limit_qps),
+            reverse: ReverseResolver::default(),
+            stats: Stats::default(),
+            last_reload: Mutex::new(None),
+            disabled_until: AtomicU64::new(0),
+            started: Instant::now(),
+        };
+        blocker.reload_blocklist().await?;
+        // Un cache illisible ne doit pas empêcher le démarrage
+        match blocker.cache.load().await {
+            Ok(restored) => info!(restored, "Cache DNS restauré"),
+            Err(e) => warn!(error = format!("{:#}", e), "Impossible de restaurer le cache DNS"),
+        }
+        Ok(blocker)
+    }
+
+    // En cas d'erreur de lecture, l'ancienne version reste en place
+    pub async fn reload_hosts_file(&self, path: &Path) {
+        match HostsFile::load(path).await {
+            Ok(hosts) => {
+                info!(path = %path.display(), records = hosts.count(), "Fichier hosts chargé");
+                *self.hosts_file.write().unwrap() = hosts;
+            }
+            Err(e) => error!(path = %path.display(), error = format!("{:#}", e), "Impossible de lire le fichier hosts"),
+        }
+    }
+
+    // Appelé à l'arrêt pour éviter un démarrage à froid
+    pub async fn save_cache(&self) {
+        match self.cache.save().await {
+            Ok(saved) => info!(saved, "Cache DNS sauvegardé"),
+            Err(e) => error!(error = format!("{:#}", e), "Impossible de sauvegarder le cache DNS"),
+        }
+    }
+
+    // Recharge les listes depuis la base et les remplace d'un coup
+    pub async fn reload_blocklist(&self) -> anyhow::Result<usize> {
+        // Un domaine présent dans plusieurs sources n'est gardé qu'une fois
+        let rows = get_active_blocked_domains().await?;
+        let row_count = rows.len();
+        let mut blocked = DomainTrie::default();
+        for row in &rows {
+            blocked.insert_from(&row.domain, row.source_id);
+        }
+        let source_names: HashMap<i64, String> = get_all_blocklist_sources()
+            .await?
+            .iter()
+            .map(|source| (source.id, source_name(source)))
+            .collect();
+        let allowed: DomainTrie = get_all_allowed_domains()
+            .await?
+            .into_iter()
+            .map(|d| d.domain)
+            .collect();
+        let regex_rules: Vec<Regex> = get_all_regex_rules()
+            .await?
+            .into_iter()
+            .filter_map(|rule| match Regex::new(&rule.pattern) {
+                Ok(regex) => Some(regex),
+                Err(e) => {
+                    warn!(id = rule.id, pattern = %rule.pattern, error = %e, "Motif invalide ignoré");
+                    None
+                }
+            })
+            .collect();
+        let groups = ClientGroups::load().await?;
+        let local_records = LocalRecords::load().await?;
+
+        let count = blocked.len();
+        let allowed_count = allowed.len();
+        let regex_count = regex_rules.len();
+        let client_count = groups.clients.len();
+        let local_count = local_records.count();
+        *self.blocked.write().unwrap() = blocked;
+        *self.source_names.write().unwrap() = source_names;
+        *self.allowed.write().unwrap() = allowed;
+        *self.regex_rules.write().unwrap() = regex_rules;
+        *self.groups.write().unwrap() = groups;
+        *self.local_records.write().unwrap() = local_records;
+        *self.last_reload.lock().unwrap() = Some(SystemTime::now());
+        gauge!("rusthole_blocklist_domains").set(count as f64);
+        info!(
+            blocked = count,
+            duplicates = row_count - count,
+            allowed = allowed_count,
+            regex = regex_count,
+            clients = client_count,
+            local_records = local_count,
+            "Blocklist rechargée"
+        );
+        Ok(count)
+    }
+
+    // Un domaine ajouté pendant un rechargement en cours peut être écrasé par
+    // la blocklist lue juste avant : il revient au rechargement suivant
+    pub async fn add_domain_live(&self, domain: &str) -> anyhow::Result<Option<BlockedDomainModel>> {
+        let Some(model) = insert_blocked_domain(domain).await? else {
+            return Ok(None);
+        };
+        let mut blocked = self.blocked.write().unwrap();
+        blocked.insert(&model.domain);
+        gauge!("rusthole_blocklist_domains").set(blocked.len() as f64);
+        info!(domain = %model.domain, "Domaine ajouté à la blocklist");
+        Ok(Some(model))
+    }
+
+    pub async fn remove_domain_live(&self, id: u32) -> anyhow::Result<bool> {
+        let Some(model) = delete_blocked_domain(id).await? else {
+            return Ok(false);
+        };
+        // Le domaine reste bloqué s'il figure aussi dans une source active
+        let remaining = find_active_blocked_domain(&model.domain).await?;
+        let mut blocked = self.blocked.write().unwrap();
+        blocked.remove(&model.domain);
+        if let Some(row) = remaining {
+            blocked.insert_from(&row.domain, row.source_id);
+        }
+        gauge!("rusthole_blocklist_domains").set(blocked.len() as f64);
+        info!(domain = %model.domain, "Domaine retiré de la blocklist");
+        Ok(true)
+    }
+
+    // Point de sortie unique des réponses : comptabilise l'issue de la
+    // requête, la journalise avec l'étape qui a répondu et rend la réponse
+    // telle quelle
+    fn respond(
+        &self,
+        client: IpAddr,
+        name: &str,
+        rtype: RecordType,
+        status: QueryStatus,
+        source: QuerySource,
+        resp: Message,
+    ) -> Message {
+        self.stats.record(status, rtype);
+        counter!("rusthole_queries_total", "status" => status.as_str()).increment(1);
+        if !self.query_log.sample(source) {
+            return resp;
+        }
+        macro_rules! query_line {
+            ($level:ident) => {
+                $level!(
+                    domain = name,
+                    client = %client,
+                    rtype = %rtype,
+                    outcome = status.as_str(),
+                    source = source.as_str(),
+                    rcode = %resp.response_code(),
+                    answers = resp.answer_count(),
+                    "Requête DNS"
+                )
+            };
+        }
+        let notable = matches!(status, QueryStatus::Blocked | QueryStatus::ServFail | QueryStatus::FormErr);
+        match self.query_log.verbosity() {
+            LogVerbosity::Off => {}
+            LogVerbosity::Summary if !notable => query_line!(debug),
+            LogVerbosity::Summary | LogVerbosity::Verbose => query_line!(info),
+        }
+        self.query_log.log(client, name, rtype, status, source);
+        resp
+    }
+
+    // Durée restante de la suspension, qui est levée une fois écoulée
+    fn blocking_disabled_for(&self) -> Option<Duration> {
+        let until = self.disabled_until.load(Ordering::Relaxed);
+        if until == 0 {
+            return None;
+        }
+        let now = self.elapsed_ms();
+        if until <= now {
+            // Une seule requête lève la suspension et le journalise
+            let expired = self
+                .disabled_until
+                .compare_exchange(until, 0, Ordering::Relaxed, Ordering::Relaxed);
+            if expired.is_ok() {
+                info!("Blocage réactivé");
+            }
+            return None;
+        }
+        Some(Duration::from_millis(until - now))
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        u64::try_from(self.started.elapsed().as_millis()).unwrap_or(u64::MAX)
+    }
+
+    fn is_allowed(&self, name: &str) -> bool {
+        self.allowed.read().unwrap().matches(name)
+    }
+
+    // Décision de blocage, commune aux requêtes DNS et à GET /check pour
+    // qu'elles ne puissent pas diverger. L'allowlist passe avant la blocklist,
+    // même quand elle est plus précise que le suffixe bloqué (safe.example.com
+    // autorisé dans example.com bloqué)
+    pub fn check_domain(&self, client: IpAddr, name: &str) -> DomainCheck {
+        let allowlisted = self.is_allowed(name);
+        let (matched_rule, source) = self.matched_rule(client, name).unzip();
+        DomainCheck {
+            blocked: self.blocking_disabled_for().is_none() && !allowlisted && matched_rule.is_some(),
+            matched_rule,
+            source,
+            allowlisted,
+        }
+    }
+
+    // Un client IPv4 vu en ::ffff:a.b.c.d (écoute sur [::]) reste dans ses sous-réseaux IPv4
+    fn client_allowed(&self, client: IpAddr) -> bool {
+        let client = client.to_canonical();
+        self.allowed_clients.is_empty() || self.allowed_clients.iter().any(|net| net.contains(&client))
+    }
+
+    // Blocklist par défaut, puis celle du groupe du client s'il en a un, puis
+    // les motifs. Règle décrite telle qu'affichée par /check, et d'où elle vient
+    fn matched_rule(&self, client: IpAddr, name: &str) -> Option<(String, String)> {
+        if let Some((entry, source)) = self.blocked.read().unwrap().find_with_source(name) {
+            // Sans source : ajouté à la main (API ou dashboard)
+            let source = source
+                .and_then(|id| self.source_names.read().unwrap().get(&id).cloned())
+                .unwrap_or_else(|| "manual".to_string());
+            return Some((entry.to_string(), source));
+        }
+        if let Some((group, entry)) = self.groups.read().unwrap().find(client, name) {
+            return Some((format!("group {}: {}", group, entry), format!("group {}", group)));
+        }
+        self.regex_rules
+            .read()
+            .unwrap()
+            .iter()
+            .find(|r| r.is_match(name))
+            .map(|r| (format!("regex: {}", r.as_str()), "regex".to_string()))
+    }
+
+    // CNAME cloaking : un tracker caché derrière un sous-domaine « maison ».
+    // Vérifié à chaque réponse, cache compris, car la blocklist dépend du client.
+    fn blocked_cname(&self, client: IpAddr, name: &str, resp: &Message) -> Option<String> {
+        if self.blocking_disabled_for().is_some() || self.is_allowed(name) {
+            return None;
+        }
+        resp.answers()
+            .iter()
+            .filter_map(|r| match r.data() {
+                Some(RData::CNAME(target)) => {
+                    Some(target.0.to_ascii().trim_end_matches('.').to_ascii_lowercase())
+                }
+                _ => None,
+            })
+            .find(|target| self.check_domain(client, target).blocked)
+    }
 
+    fn blocked_response(&self, msg: &Message, query: &Query) -> Message {
+        match self.blocking_mode {
+            BlockingMode::NxDomain => {
+                let mut resp = empty_response(msg, query, ResponseCode::NXDomain);
+                resp.add_name_server(self.blocked_soa(query));
+                resp
+            }
+            BlockingMode::Refused => empty_response(msg, query, ResponseCode::Refused),
+            BlockingMode::NullIp => {
+                let mut resp = empty_response(msg, query, ResponseCode::NoError);
+                let rdata = match query.query_type() {
+                    RecordType::A => Some(RData::A(A(Ipv4Addr::UNSPECIFIED))),
+                    RecordType::AAAA => Some(RData::AAAA(AAAA(Ipv6Addr::UNSPECIFIED))),
+                    // Autres types : réponse vide (NODATA)
+                    _ => None,
+                };
+                match rdata {
+                    Some(rdata) => resp.add_answer(Record::from_rdata(
+                        query.name().clone(),
+                        self.blocked_ttl,
+                        rdata,
+                    )),
+                    None => resp.add_name_server(self.blocked_soa(query)),
+                };
+                resp
+            }
+        }
+    }
+
+    fn block_reason_response(&self, msg: &Message, query: &Query, source: &str) -> Message {
+        let mut resp = empty_response(msg, query, ResponseCode::NoError);
+        let reason = TXT::new(vec![format!("blocked by rust-hole: source={}", source)]);
+        resp.add_answer(Record::from_rdata(query.name().clone(), self.blocked_ttl, RData::TXT(reason)));
+        resp
+    }
+
+    // Sans SOA, la durée du cache négatif est laissée au choix du client (RFC 2308)
+    fn blocked_soa(&self, query: &Query) -> Record {
+        let ttl = self.blocked_ttl;
+        let timer = i32::try_from(ttl).unwrap_or(i32::MAX);
+        let soa = SOA::new(
+            self.soa_mname.clone(),
+            self.soa_rname.clone(),
+            1,
+            timer,
+            timer,
+            timer,
+            ttl,
+        );
+        Record::from_rdata(query.name().clone(), ttl, RData::SOA(soa))
+    }
+}
+
+// Option EDNS Client Subnet (RFC 7871) de la requête, encodée
+fn client_subnet(msg: &Message) -> Option<Vec<u8>> {
+    match msg.extensions().as_ref()?.options().get(EdnsCode::Subnet)? {
+        EdnsOption::Subnet(subnet) => Vec::<u8>::try_from(subnet).ok(),
+        _ => None,
+    }
+}
+
+fn empty_response(msg: &Message, query: &Query, code: ResponseCode) -> Message {
+    let mut resp = Message::new();
+    resp.set_id(msg.id());
+    resp.set_message_type(MessageType::Response);
+    resp.set_op_code(OpCode::Query);
+    resp.set_recursion_desired(msg.recursion_desired());
+    resp.set_recursion_available(true);
+    resp.add_query(query.clone());
+    resp.set_response_code(code);
+    resp
+}
+
+// Une réponse partagée (cache, requête en attente) porte la question de celui
+// qui l'a obtenue : le client retrouve la casse qu'il a envoyée
+fn echo_question(resp: &mut Message, query: &Query) {
+    resp.take_queries();
+    resp.add_query(query.clone());
+}
+
+#[async_trait]
+impl DnsHandle for DnsBlocker {
+    async fn reload_blocklist(&self) -> anyhow::Result<usize> {
+        DnsBlocker::reload_blocklist(self).await
+    }
+
+    async fn add_domain_live(&self, domain: &str) -> anyhow::Result<Option<BlockedDomainModel>> {
+        DnsBlocker::add_domain_live(self, domain).await
+    }
+
+    async fn remove_domain_live(&self, id: u32) -> anyhow::Result<bool> {
+        DnsBlocker::remove_domain_live(self, id).await
+    }
+
+    fn stats(&self) -> StatsSnapshot {
+        let last_reload = self
+            .last_reload
+            .lock()
+            .unwrap()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64);
+        let blocklist = BlocklistInfo {
+            domains: self.blocked.read().unwrap().len(),
+            last_reload,
+        };
+        self.stats.snapshot(self.blocking_disabled_for(), blocklist)
+    }
+
+    fn disable_blocking(&self, duration: Duration) {
+        let duration_ms = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+        let until = self.elapsed_ms().saturating_add(duration_ms).max(1);
+        self.disabled_until.store(until, Ordering::Relaxed);
+        info!(seconds = duration.as_secs(), "Blocage suspendu");
+    }
+
+    fn enable_blocking(&self) {
+        if self.disabled_until.swap(0, Ordering::Relaxed) != 0 {
+            info!("Blocage réactivé");
+        }
+    }
+
+    fn subscribe_queries(&self) -> broadcast::Receiver<QueryEvent> {
+        self.query_log.subscribe()
+    }
+
+    async fn reverse_lookup(&self, ip: IpAddr) -> Option<String> {
+        self.reverse.lookup(ip, &self.upstream, &self.conditional).await
+    }
+
+    async fn handle_query(&self, query: &[u8], client: IpAddr) -> anyhow::Result<Option<Vec<u8>>> {
+        self.handle_request(query, client).await
+    }
+
+    fn cache_snapshot(&self) -> Vec<CacheEntryInfo> {
+        self.cache.snapshot()
+    }
+
+    fn check_domain(&self, client: IpAddr, domain: &str) -> DomainCheck {
+        DnsBlocker::check_domain(self, client, domain)
+    }
+
+    fn flush_cache(&self, domain: Option<&str>) -> usize {
+        self.cache.flush(domain)
+    }
+}
+
+// ================= Handler =================
+impl DnsBlocker {
+    // Retourne la réponse encodée, ou `None` si la requête est illisible.
+    // Toute requête lisible reçoit une réponse, au pire un SERVFAIL
+    pub async fn handle_request(
+        &self,
+        req_bytes: &[u8],
+        client: IpAddr,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
         let msg = match Message::from_vec(req_bytes) {
             Ok(m) => m,
-            Err(_) => continue,
+            Err(_) => return Ok(None),
         };
 
-        let query = match msg.queries().first() {
-            Some(q) => q,
-            None => continue,
+        // Pas de question : FORMERR plutôt que le silence
+        let Some(query) = msg.queries().first() else {
+            let mut resp = Message::new();
+            resp.set_id(msg.id());
+            resp.set_message_type(MessageType::Response);
+            resp.set_op_code(msg.op_code());
+            resp.set_response_code(ResponseCode::FormErr);
+            // Type 0, réservé : la requête n'en porte aucun
+            let resp = self.respond(client, "", RecordType::ZERO, QueryStatus::FormErr, QuerySource::Error, resp);
+            return Ok(Some(resp.to_vec()?));
         };
 
-        let name = query.name().to_utf8().trim_end_matches('.').to_string();
+        // Les listes sont en minuscules (normalize_domain), la casse de la requête est libre
+        let name = query.name().to_ascii().trim_end_matches('.').to_ascii_lowercase();
+        let resp = self
+            .resolve(&msg, query, &name, req_bytes, client)
+            .await
+            .and_then(|resp| Ok(resp.to_vec()?));
+        match resp {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) => {
+                error!(domain = %query.name(), error = format!("{:#}", e), "Réponse impossible, ServFail");
+                let resp = empty_response(&msg, query, ResponseCode::ServFail);
+                let rtype = query.query_type();
+                let resp = self.respond(client, &name, rtype, QueryStatus::ServFail, QuerySource::Error, resp);
+                Ok(Some(resp.to_vec()?))
+            }
+        }
+    }
+
+    async fn resolve(
+        &self,
+        msg: &Message,
+        query: &Query,
+        name: &str,
+        req_bytes: &[u8],
+        client: IpAddr,
+    ) -> anyhow::Result<Message> {
+        let received = Instant::now();
         let rtype = query.query_type();
 
+        // ---------- ACCESS ----------
+        if !self.client_allowed(client) {
+            counter!("rusthole_refused_clients_total").increment(1);
+            let resp = empty_response(msg, query, ResponseCode::Refused);
+            return Ok(self.respond(client, name, rtype, QueryStatus::Refused, QuerySource::Refused, resp));
+        }
+
+        // ---------- RATE LIMIT ----------
+        if !self.rate_limiter.check(client) {
+            self.stats.record_rate_limited();
+            counter!("rusthole_rate_limited_total").increment(1);
+            let resp = empty_response(msg, query, ResponseCode::Refused);
+            return Ok(self.respond(client, name, rtype, QueryStatus::Refused, QuerySource::RateLimited, resp));
+        }
+
         // ---------- BLOCK ----------
-        if blocked.iter().any(|d| name.ends_with(d)) {
-            let mut resp = Message::new();
-            resp.set_id(msg.id());
-            resp.set_message_type(MessageType::Response);
-            resp.set_op_code(OpCode::Query);
-            resp.set_response_code(ResponseCode::NXDomain);
+        let check = self.check_domain(client, name);
+        if check.blocked {
+            let resp = match check.source {
+                Some(source) if rtype == RecordType::TXT && self.txt_reason => {
+                    self.block_reason_response(msg, query, &source)
+                }
+                _ => self.blocked_response(msg, query),
+            };
+            return Ok(self.respond(client, name, rtype, QueryStatus::Blocked, QuerySource::Blocked, resp));
+        }
 
-            let bytes = resp.to_vec()?;
-            socket.send_to(&bytes, peer).await?;
-            continue;
+        // ---------- LOCAL ----------
+        // Le fichier hosts passe avant les enregistrements de la base
+        let hosts = self.hosts_file.read().unwrap().lookup(name, rtype);
+        let local = hosts.or_else(|| self.local_records.read().unwrap().lookup(name, rtype));
+        if let Some(answers) = local {
+            let mut resp = empty_response(msg, query, ResponseCode::NoError);
+            resp.set_authoritative(true);
+            resp.add_answers(answers);
+            return Ok(self.respond(client, name, rtype, QueryStatus::Local, QuerySource::Local, resp));
         }
 
+        // ---------- ANY ----------
+        if rtype == RecordType::ANY && !self.forward_any {
+            let mut resp = empty_response(msg, query, ResponseCode::NoError);
+            let hinfo = HINFO::new(ANY_HINFO_CPU.to_string(), String::new());
+            resp.add_answer(Record::from_rdata(query.name().clone(), ANY_HINFO_TTL, RData::HINFO(hinfo)));
+            return Ok(self.respond(client, name, rtype, QueryStatus::Local, QuerySource::Local, resp));
+        }
+
+        // ---------- ECS ----------
+        // Sans passthrough, le sous-réseau du client ne quitte pas le réseau local
+        let subnet = client_subnet(msg);
+        let stripped;
+        let req_bytes = if subnet.is_some() && !self.ecs_passthrough {
+            let mut without_ecs = msg.clone();
+            if let Some(edns) = without_ecs.extensions_mut() {
+                edns.options_mut().remove(EdnsCode::Subnet);
+            }
+            stripped = without_ecs.to_vec()?;
+            &stripped[..]
+        } else {
+            req_bytes
+        };
+
         // ---------- CACHE ----------
-        let key = (name.clone(), rtype);
-        if let Some(entry) = cache.get(&key) {
-            if Instant::now() < entry.expires_at {
-                let mut resp_msg = entry.msg.clone();
-                resp_msg.set_id(msg.id()); // Fix ID
-                let bytes = resp_msg.to_vec()?;
-                socket.send_to(&bytes, peer).await?;
-                continue;
-            } else {
-                cache.remove(&key);
+        let key = CacheKey::new(name, rtype, subnet.filter(|_| self.ecs_passthrough));
+        if let Some(cached) = self.cache.get(&key) {
+            if let Some(target) = self.blocked_cname(client, name, &cached.msg) {
+                debug!(domain = %name, cname = %target, "CNAME bloqué");
+                let resp = self.blocked_response(msg, query);
+                return Ok(self.respond(client, name, rtype, QueryStatus::Blocked, QuerySource::Blocked, resp));
+            }
+            let mut resp_msg = cached.msg;
+            resp_msg.set_id(msg.id()); // Fix ID
+            echo_question(&mut resp_msg, query);
+            if cached.negative {
+                self.stats.record_negative_hit();
             }
+            self.stats.cache_latency.record(received.elapsed());
+            return Ok(self.respond(client, name, rtype, QueryStatus::Cached, QuerySource::Cache, resp_msg));
         }
 
+        // ---------- COALESCING ----------
+        let leader = match self.pending.join(&key) {
+            Role::Leader(leader) => {
+                self.stats.record_pending(false);
+                counter!("rusthole_pending_queries_total", "role" => "leader").increment(1);
+                leader
+            }
+            Role::Waiter(rx) => {
+                self.stats.record_pending(true);
+                counter!("rusthole_pending_queries_total", "role" => "coalesced").increment(1);
+                return Ok(match rx.await.unwrap_or(Err(ResponseCode::ServFail)) {
+                    Ok(resp_msg) => self.forwarded_response(msg, query, client, name, resp_msg),
+                    Err(code) => {
+                        let resp = empty_response(msg, query, code);
+                        self.respond(client, name, rtype, QueryStatus::ServFail, QuerySource::Forwarded, resp)
+                    }
+                });
+            }
+        };
+
         // ---------- FORWARD RAW ----------
-        upstream_socket.send_to(req_bytes, upstream).await?;
+        counter!("rusthole_cache_misses_total").increment(1);
+        let upstream = self.conditional.select(name).unwrap_or(&self.upstream);
+        let validating;
+        let req_bytes = if upstream.dnssec {
+            validating = upstream::validating_request(req_bytes)?;
+            &validating[..]
+        } else {
+            req_bytes
+        };
+        let started = Instant::now();
+        let result = upstream::forward(upstream, req_bytes, msg.id()).await;
+        let elapsed = started.elapsed();
+        histogram!("rusthole_upstream_latency_seconds").record(elapsed.as_secs_f64());
+        if result.is_ok() {
+            self.stats.upstream_latency.record(elapsed);
+        }
 
-        let mut resp_buf = [0u8; 4096];
-        let (resp_len, _) = upstream_socket.recv_from(&mut resp_buf).await?;
-        let resp_bytes = &resp_buf[..resp_len];
+        let resp_msg = match result {
+            Ok(m) => m,
+            Err(e) => {
+                counter!("rusthole_upstream_errors_total").increment(1);
+                warn!(domain = %name, error = format!("{:#}", e), "ServFail");
+                leader.finish(&Err(ResponseCode::ServFail));
+                let resp = empty_response(msg, query, ResponseCode::ServFail);
+                return Ok(self.respond(client, name, rtype, QueryStatus::ServFail, QuerySource::Forwarded, resp));
+            }
+        };
 
-        let mut resp_msg = Message::from_vec(resp_bytes)?;
+        // ---------- DNSSEC ----------
+        if upstream.dnssec && upstream::dnssec_failed(&resp_msg) {
+            warn!(domain = %name, "Validation DNSSEC en échec, ServFail");
+            self.stats.record_dnssec_failure();
+            counter!("rusthole_dnssec_failures_total").increment(1);
+            leader.finish(&Err(ResponseCode::ServFail));
+            let resp = empty_response(msg, query, ResponseCode::ServFail);
+            return Ok(self.respond(client, name, rtype, QueryStatus::ServFail, QuerySource::Forwarded, resp));
+        }
 
         // ---------- CACHE STORE ----------
-        let ttl = resp_msg
-            .answers()
-            .iter()
-            .map(|r| r.ttl())
-            .min()
-            .unwrap_or(60);
+        // Avant de libérer les requêtes en attente : les suivantes iront au cache
+        self.cache.store(key, &resp_msg);
+        leader.finish(&Ok(resp_msg.clone()));
 
-        let cache_entry = CacheEntry {
-            msg: resp_msg.clone(),
-            expires_at: Instant::now() + Duration::from_secs(ttl as u64),
-        };
-        cache.insert(key, cache_entry);
+        Ok(self.forwarded_response(msg, query, client, name, resp_msg))
+    }
+
+    // Réponse de l'upstream adaptée au client, partagée entre le leader et
+    // les requêtes en attente
+    fn forwarded_response(
+        &self,
+        msg: &Message,
+        query: &Query,
+        client: IpAddr,
+        name: &str,
+        mut resp_msg: Message,
+    ) -> Message {
+        let rtype = query.query_type();
+
+        // ---------- CNAME CLOAKING ----------
+        if let Some(target) = self.blocked_cname(client, name, &resp_msg) {
+            debug!(domain = %name, cname = %target, "CNAME bloqué");
+            let resp = self.blocked_response(msg, query);
+            return self.respond(client, name, rtype, QueryStatus::Blocked, QuerySource::Blocked, resp);
+        }
 
         // Fix ID et envoyer
         resp_msg.set_id(msg.id());
-        let fixed_bytes = resp_msg.to_vec()?;
-        socket.send_to(&fixed_bytes, peer).await?;
+        echo_question(&mut resp_msg, query);
+        self.respond(client, name, rtype, QueryStatus::Allowed, QuerySource::Forwarded, resp_msg)
+    }
+}
+
+// ================= DNS Server =================
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+const RATE_LIMIT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+pub const DEFAULT_DNS_ADDR: &str = "127.0.0.2:53";
+
+// `fallback_port` : port non privilégié essayé si `addr` est refusé faute de
+// droits, uniquement s'il est configuré
+pub async fn run_dns(
+    blocker: Arc<DnsBlocker>,
+    addr: SocketAddr,
+    fallback_port: Option<u16>,
+    cache_sweep_interval: Duration,
+) -> anyhow::Result<()> {
+    let (socket, listener, addr) = match bind_dns(addr).await {
+        Ok((socket, listener)) => (socket, listener, addr),
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            let Some(port) = fallback_port else {
+                return Err(permission_denied(addr));
+            };
+            let fallback = SocketAddr::new(addr.ip(), port);
+            warn!(
+                %addr,
+                %fallback,
+                "Port DNS refusé faute de privilèges, écoute sur le port de repli : \
+                 les clients doivent l'interroger explicitement"
+            );
+            let (socket, listener) = bind_dns(fallback).await.map_err(|e| {
+                anyhow::anyhow!("Impossible de lier le port DNS de repli {}: {}", fallback, e)
+            })?;
+            (socket, listener, fallback)
+        }
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            return Err(anyhow::anyhow!(
+                "Le port DNS {} est déjà utilisé (systemd-resolved, dnsmasq ou une autre instance ?)",
+                addr
+            ));
+        }
+        Err(e) => return Err(anyhow::anyhow!("Impossible de lier le port DNS {}: {}", addr, e)),
+    };
+
+    info!(%addr, "Serveur DNS démarré (UDP/TCP)");
+    tokio::spawn(run_tcp(listener, blocker.clone()));
+    tokio::spawn(sweep_cache(blocker.clone(), cache_sweep_interval));
+    tokio::spawn(sweep_rate_limits(blocker.clone()));
+    tokio::spawn(probe_upstream(blocker.clone()));
+    run_udp(Arc::new(socket), blocker).await
+}
+
+// Même adresse en UDP et en TCP
+async fn bind_dns(addr: SocketAddr) -> std::io::Result<(UdpSocket, TcpListener)> {
+    let socket = UdpSocket::bind(addr).await?;
+    let listener = TcpListener::bind(addr).await?;
+    Ok((socket, listener))
+}
+
+fn permission_denied(addr: SocketAddr) -> anyhow::Error {
+    let exe = std::env::current_exe()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|_| "rust-hole-core".to_string());
+    anyhow::anyhow!(
+        "Permission refusée pour lier le port DNS {addr}. Les ports inférieurs à 1024 \
+         nécessitent root ou la capacité CAP_NET_BIND_SERVICE :\n  \
+         sudo setcap 'cap_net_bind_service=+ep' {exe}\n\
+         (AmbientCapabilities=CAP_NET_BIND_SERVICE dans une unité systemd). Pour tester \
+         sans privilèges, choisir un port libre avec RUSTHOLE_DNS_ADDR ou activer \
+         RUSTHOLE_DNS_FALLBACK_PORT"
+    )
+}
+
+// Les entrées expirées ne sont sinon retirées qu'à la prochaine requête identique
+async fn sweep_cache(blocker: Arc<DnsBlocker>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        let purged = blocker.cache.purge_expired();
+        if purged > 0 {
+            info!(purged, "Entrées expirées supprimées du cache");
+        }
+    }
+}
+
+// Un upstream mal configuré se voit ici plutôt qu'en ServFail à la première requête
+async fn probe_upstream(blocker: Arc<DnsBlocker>) {
+    let Ok(name) = Name::from_ascii(PROBE_DOMAIN) else { return };
+    let started = Instant::now();
+    match upstream::query(&blocker.upstream, name, RecordType::A).await {
+        Ok(resp) if resp.response_code() == ResponseCode::NoError => {
+            info!(domain = PROBE_DOMAIN, elapsed = ?started.elapsed(), "Upstream joignable");
+        }
+        Ok(resp) => warn!(
+            domain = PROBE_DOMAIN,
+            rcode = %resp.response_code(),
+            "Réponse inattendue de l'upstream au démarrage"
+        ),
+        Err(e) => warn!(
+            domain = PROBE_DOMAIN,
+            error = format!("{:#}", e),
+            "Upstream injoignable au démarrage, vérifier RUSTHOLE_UPSTREAM"
+        ),
+    }
+}
+
+// Surveille le dossier plutôt que le fichier : les éditeurs remplacent souvent
+// le fichier au lieu de le modifier, et il peut ne pas exister encore
+pub async fn watch_hosts_file(blocker: Arc<DnsBlocker>, path: PathBuf) {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = match notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    }) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            error!(error = %e, "Impossible de surveiller le fichier hosts");
+            return;
+        }
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+        error!(path = %dir.display(), error = %e, "Impossible de surveiller le fichier hosts");
+        return;
+    }
+
+    while let Some(event) = rx.recv().await {
+        match event {
+            Ok(event)
+                if !matches!(event.kind, EventKind::Access(_))
+                    && event.paths.iter().any(|p| p.file_name() == path.file_name()) =>
+            {
+                blocker.reload_hosts_file(&path).await;
+            }
+            Ok(_) => {}
+            Err(e) => warn!(error = %e, "Erreur de surveillance du fichier hosts"),
+        }
+    }
+}
+
+async fn sweep_rate_limits(blocker: Arc<DnsBlocker>) {
+    let mut ticker = tokio::time::interval(RATE_LIMIT_SWEEP_INTERVAL);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        let purged = blocker.rate_limiter.purge_idle();
+        if purged > 0 {
+            debug!(purged, "Clients inactifs retirés du rate limit");
+        }
+    }
+}
+
+async fn run_udp(socket: Arc<UdpSocket>, blocker: Arc<DnsBlocker>) -> anyhow::Result<()> {
+    let mut buf = [0u8; 4096];
+
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).await?;
+        let req_bytes = buf[..len].to_vec();
+
+        let socket = socket.clone();
+        let blocker = blocker.clone();
+        tokio::spawn(async move {
+            match blocker.handle_request(&req_bytes, peer.ip()).await {
+                Ok(Some(resp)) => {
+                    if let Err(e) = socket.send_to(&resp, peer).await {
+                        error!(client = %peer, error = %e, "Erreur d'envoi UDP");
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => error!(client = %peer, error = format!("{:#}", e), "Erreur UDP"),
+            }
+        });
+    }
+}
+
+async fn run_tcp(listener: TcpListener, blocker: Arc<DnsBlocker>) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!(error = %e, "Erreur d'acceptation TCP");
+                continue;
+            }
+        };
+
+        let blocker = blocker.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_tcp_connection(stream, peer.ip(), &blocker).await {
+                error!(client = %peer, error = format!("{:#}", e), "Erreur TCP");
+            }
+        });
+    }
+}
+
+// Plusieurs requêtes peuvent se suivre sur une même connexion
+async fn serve_tcp_connection(
+    mut stream: TcpStream,
+    client: IpAddr,
+    blocker: &DnsBlocker,
+) -> anyhow::Result<()> {
+    loop {
+        let len = match timeout(TCP_IDLE_TIMEOUT, stream.read_u16()).await {
+            Ok(Ok(len)) => len as usize,
+            // Connexion fermée par le client ou inactive
+            Ok(Err(_)) | Err(_) => return Ok(()),
+        };
+
+        let mut req_bytes = vec![0u8; len];
+        timeout(TCP_IDLE_TIMEOUT, stream.read_exact(&mut req_bytes)).await??;
+
+        let Some(resp) = blocker.handle_request(&req_bytes, client).await? else {
+            return Ok(());
+        };
+        stream.write_all(&u16::try_from(resp.len())?.to_be_bytes()).await?;
+        stream.write_all(&resp).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::testing::{answer, record, reply, MockUpstream, Request, TestBlocker, CLIENT};
+
+    fn a_record(name: &str, ip: [u8; 4]) -> Record {
+        record(name, 300, RData::A(A::from(Ipv4Addr::from(ip))))
+    }
+
+    // Upstream qui répond 93.184.216.34 à toute requête A
+    async fn upstream() -> MockUpstream {
+        MockUpstream::start(|req| {
+            let name = req.queries()[0].name().to_ascii();
+            Some(answer(req, vec![a_record(&name, [93, 184, 216, 34])]))
+        })
+        .await
+    }
+
+    fn first_a(resp: &Message) -> Option<Ipv4Addr> {
+        resp.answers().iter().find_map(|r| match r.data() {
+            Some(RData::A(a)) => Some(a.0),
+            _ => None,
+        })
+    }
+
+    #[tokio::test]
+    async fn blocked_domain_gets_nxdomain_without_upstream() {
+        let upstream = upstream().await;
+        let blocker = TestBlocker::new(upstream.addr).start().await;
+        blocker.blocked.write().unwrap().insert("ads.blocked.test");
+
+        let req = Request::new("tracker.ads.blocked.test.", RecordType::A);
+        let resp = req.send(&blocker).await;
+        assert_eq!(resp.id(), req.id());
+        assert_eq!(resp.response_code(), ResponseCode::NXDomain);
+        assert!(resp.answers().is_empty());
+        // SOA pour le cache négatif du client
+        assert!(matches!(resp.name_servers()[0].data(), Some(RData::SOA(_))));
+        assert_eq!(upstream.queries(), 0);
+        assert_eq!(DnsHandle::stats(&blocker).blocked_queries, 1);
+    }
+
+    #[tokio::test]
+    async fn blocklist_matches_whole_labels_only() {
+        let upstream = upstream().await;
+        let blocker = TestBlocker::new(upstream.addr).start().await;
+        blocker.blocked.write().unwrap().insert("labels.test");
+
+        for (name, blocked) in [
+            ("labels.test", true),
+            ("www.labels.test", true),
+            ("notlabels.test", false),
+            ("labels.test.evil.com", false),
+        ] {
+            let check = blocker.check_domain(CLIENT, name);
+            assert_eq!(check.blocked, blocked, "{name}");
+            let expected = if blocked { ResponseCode::NXDomain } else { ResponseCode::NoError };
+            let resp = Request::new(&format!("{name}."), RecordType::A).send(&blocker).await;
+            assert_eq!(resp.response_code(), expected, "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn entries_with_a_trailing_dot_still_block() {
+        let upstream = upstream().await;
+        let blocker = TestBlocker::new(upstream.addr).start().await;
+        insert_blocked_domain("Ads.Trailing.Test.").await.unwrap().unwrap();
+        blocker.reload_blocklist().await.unwrap();
+
+        for name in ["ads.trailing.test.", "ADS.trailing.test.", "x.ads.trailing.test."] {
+            let resp = Request::new(name, RecordType::A).send(&blocker).await;
+            assert_eq!(resp.response_code(), ResponseCode::NXDomain, "{name}");
+        }
+        assert!(blocker.check_domain(CLIENT, "ads.trailing.test").blocked);
+        assert_eq!(upstream.queries(), 0);
+    }
+
+    #[tokio::test]
+    async fn allowlist_wins_over_a_broader_blocked_suffix() {
+        let upstream = upstream().await;
+        let blocker = TestBlocker::new(upstream.addr).start().await;
+        blocker.blocked.write().unwrap().insert("allow.test");
+        blocker.allowed.write().unwrap().insert("safe.allow.test");
+
+        let resp = Request::new("safe.allow.test.", RecordType::A).send(&blocker).await;
+        assert_eq!(resp.response_code(), ResponseCode::NoError);
+        assert_eq!(first_a(&resp), Some(Ipv4Addr::new(93, 184, 216, 34)));
+        let resp = Request::new("cdn.safe.allow.test.", RecordType::A).send(&blocker).await;
+        assert_eq!(resp.response_code(), ResponseCode::NoError);
+
+        let resp = Request::new("ads.allow.test.", RecordType::A).send(&blocker).await;
+        assert_eq!(resp.response_code(), ResponseCode::NXDomain);
+        let check = blocker.check_domain(CLIENT, "safe.allow.test");
+        assert!(check.allowlisted && !check.blocked);
+        assert_eq!(check.matched_rule.as_deref(), Some("allow.test"));
+    }
+
+    #[tokio::test]
+    async fn allowed_domain_is_forwarded_then_cached() {
+        let upstream = upstream().await;
+        let blocker = TestBlocker::new(upstream.addr).start().await;
+
+        let req = Request::new("www.forwarded.test.", RecordType::A);
+        let resp = req.send(&blocker).await;
+        assert_eq!(resp.id(), req.id());
+        assert_eq!(resp.response_code(), ResponseCode::NoError);
+        assert_eq!(first_a(&resp), Some(Ipv4Addr::new(93, 184, 216, 34)));
+
+        // Même question, autre identifiant : servie depuis le cache
+        let again = Request::new("www.forwarded.test.", RecordType::A);
+        let cached = again.send(&blocker).await;
+        assert_eq!(cached.id(), again.id());
+        assert_eq!(first_a(&cached), Some(Ipv4Addr::new(93, 184, 216, 34)));
+        assert_eq!(upstream.queries(), 1);
+
+        let stats = DnsHandle::stats(&blocker);
+        assert_eq!((stats.forwarded_queries, stats.cache_hits), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn null_ip_mode_answers_each_address_family() {
+        let upstream = upstream().await;
+        let mut config = TestBlocker::new(upstream.addr);
+        config.blocking.mode = BlockingMode::NullIp;
+        let blocker = config.start().await;
+        blocker.blocked.write().unwrap().insert("nullip.test");
+
+        let resp = Request::new("nullip.test.", RecordType::AAAA).send(&blocker).await;
+        assert_eq!(resp.response_code(), ResponseCode::NoError);
+        assert_eq!(resp.answers().len(), 1);
+        assert_eq!(resp.answers()[0].record_type(), RecordType::AAAA);
+        assert_eq!(resp.answers()[0].data(), Some(&RData::AAAA(AAAA(Ipv6Addr::UNSPECIFIED))));
+
+        let resp = Request::new("nullip.test.", RecordType::A).send(&blocker).await;
+        assert_eq!(resp.answers()[0].data(), Some(&RData::A(A(Ipv4Addr::UNSPECIFIED))));
+
+        // Pas d'adresse nulle pour les autres types : NODATA
+        let resp = Request::new("nullip.test.", RecordType::MX).send(&blocker).await;
+        assert_eq!(resp.response_code(), ResponseCode::NoError);
+        assert!(resp.answers().is_empty());
+        assert_eq!(upstream.queries(), 0);
+    }
+
+    #[tokio::test]
+    async fn missing_aaaa_is_relayed_as_nodata() {
+        // A seulement : le domaine existe, sans adresse IPv6
+        let upstream = MockUpstream::start(|req| {
+            let query = &req.queries()[0];
+            let name = query.name().to_ascii();
+            let answers = match query.query_type() {
+                RecordType::A => vec![a_record(&name, [10, 0, 0, 4])],
+                _ => Vec::new(),
+            };
+            Some(answer(req, answers))
+        })
+        .await;
+        let blocker = TestBlocker::new(upstream.addr).start().await;
+
+        for _ in 0..2 {
+            let resp = Request::new("v4only.nodata.test.", RecordType::AAAA).send(&blocker).await;
+            assert_eq!(resp.response_code(), ResponseCode::NoError);
+            assert!(resp.answers().is_empty());
+        }
+        let resp = Request::new("v4only.nodata.test.", RecordType::A).send(&blocker).await;
+        assert_eq!(first_a(&resp), Some(Ipv4Addr::new(10, 0, 0, 4)));
+
+        // Le second AAAA vient du cache négatif
+        assert_eq!(upstream.queries(), 2);
+        let stats = DnsHandle::stats(&blocker);
+        assert_eq!((stats.servfail_queries, stats.negative_cache_hits), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn blocker_accepts_an_ipv6_upstream() {
+        let upstream = MockUpstream::start_v6(|req| {
+            let name = req.queries()[0].name().to_ascii();
+            Some(answer(req, vec![a_record(&name, [10, 0, 0, 6])]))
+        })
+        .await;
+        let blocker = TestBlocker::new(upstream.addr).start().await;
+
+        let resp = Request::new("www.ipv6.test.", RecordType::A).send(&blocker).await;
+        assert_eq!(first_a(&resp), Some(Ipv4Addr::new(10, 0, 0, 6)));
+        assert_eq!(upstream.queries(), 1);
+    }
+
+    #[tokio::test]
+    async fn mx_and_txt_survive_the_cache_and_a_restart() {
+        use hickory_proto::rr::rdata::MX;
+
+        let upstream = MockUpstream::start(|req| {
+            let query = &req.queries()[0];
+            let name = query.name().to_ascii();
+            let answers = match query.query_type() {
+                RecordType::MX => (1..=3)
+                    .map(|i| {
+                        let exchange = Name::from_ascii(format!("mx{i}.rdata.test.")).unwrap();
+                        record(&name, 300, RData::MX(MX::new(i * 10, exchange)))
+                    })
+                    .collect(),
+                RecordType::TXT => vec![
+                    record(&name, 300, RData::TXT(TXT::new(vec!["v=spf1 -all".into()]))),
+                    record(&name, 300, RData::TXT(TXT::new(vec!["a".repeat(255), "suite".into()]))),
+                ],
+                _ => Vec::new(),
+            };
+            Some(answer(req, answers))
+        })
+        .await;
+        let blocker = TestBlocker::new(upstream.addr).start().await;
+
+        let rdata = |resp: &Message| -> Vec<RData> {
+            resp.answers().iter().filter_map(|r| r.data().cloned()).collect()
+        };
+        let mut expected = Vec::new();
+        for rtype in [RecordType::MX, RecordType::TXT] {
+            let fresh = Request::new("rdata.test.", rtype).send(&blocker).await;
+            let cached = Request::new("rdata.test.", rtype).send(&blocker).await;
+            assert_eq!(rdata(&cached), rdata(&fresh));
+            expected.push(rdata(&fresh));
+        }
+        assert_eq!((expected[0].len(), expected[1].len()), (3, 2));
+        assert_eq!(upstream.queries(), 2);
+
+        // Cache sauvegardé puis rechargé par une nouvelle instance, sans upstream
+        blocker.save_cache().await;
+        let silent = MockUpstream::start(|_| None).await;
+        let restarted = TestBlocker::new(silent.addr).start().await;
+        for (rtype, expected) in [RecordType::MX, RecordType::TXT].into_iter().zip(&expected) {
+            let resp = Request::new("rdata.test.", rtype).send(&restarted).await;
+            assert_eq!(&rdata(&resp), expected);
+        }
+        assert_eq!(silent.queries(), 0);
+
+        let txt = restarted
+            .cache_snapshot()
+            .into_iter()
+            .find(|entry| entry.domain == "rdata.test" && entry.record_type == "TXT")
+            .unwrap();
+        assert!(txt.answers.contains(&format!("TXT \"{}\" \"suite\"", "a".repeat(255))));
+    }
+
+    #[tokio::test]
+    async fn cname_chain_is_returned_whole() {
+        use hickory_proto::rr::rdata::CNAME;
+
+        let upstream = MockUpstream::start(|req| {
+            let target = Name::from_ascii("edge.cdn.chain.test.").unwrap();
+            Some(answer(
+                req,
+                vec![
+                    record("www.chain.test.", 300, RData::CNAME(CNAME(target))),
+                    a_record("edge.cdn.chain.test.", [10, 0, 0, 9]),
+                ],
+            ))
+        })
+        .await;
+        let blocker = TestBlocker::new(upstream.addr).start().await;
+
+        for _ in 0..2 {
+            let resp = Request::new("www.chain.test.", RecordType::A).send(&blocker).await;
+            let answers: Vec<_> = resp.answers().iter().map(|r| (r.name().to_ascii(), r.record_type())).collect();
+            assert_eq!(
+                answers,
+                vec![
+                    ("www.chain.test.".to_string(), RecordType::CNAME),
+                    ("edge.cdn.chain.test.".to_string(), RecordType::A),
+                ]
+            );
+        }
+        assert_eq!(upstream.queries(), 1);
+    }
+
+    #[tokio::test]
+    async fn silent_upstream_gives_servfail() {
+        let silent = MockUpstream::start(|_| None).await;
+        let blocker = TestBlocker::new(silent.addr).start().await;
+
+        let resp = Request::new("down.servfail.test.", RecordType::A).send(&blocker).await;
+        assert_eq!(resp.response_code(), ResponseCode::ServFail);
+        assert_eq!(DnsHandle::stats(&blocker).servfail_queries, 1);
+    }
+
+    #[tokio::test]
+    async fn upstream_servfail_is_relayed_and_not_cached() {
+        let broken = MockUpstream::start(|req| Some(reply(req, ResponseCode::ServFail))).await;
+        let blocker = TestBlocker::new(broken.addr).start().await;
+
+        for _ in 0..2 {
+            let resp = Request::new("broken.servfail.test.", RecordType::A).send(&blocker).await;
+            assert_eq!(resp.response_code(), ResponseCode::ServFail);
+        }
+        assert_eq!(broken.queries(), 2);
+    }
+
+    #[tokio::test]
+    async fn identical_queries_share_one_upstream_call() {
+        let slow = MockUpstream::start_with_delay(Duration::from_millis(100), |req| {
+            let name = req.queries()[0].name().to_ascii();
+            Some(answer(req, vec![a_record(&name, [10, 0, 0, 7])]))
+        })
+        .await;
+        let blocker = Arc::new(TestBlocker::new(slow.addr).start().await);
+
+        let requests: Vec<_> = (0..10).map(|_| Request::new("stampede.test.", RecordType::A)).collect();
+        let responses = futures_util::future::join_all(requests.iter().map(|req| req.send(&blocker))).await;
+        for (req, resp) in requests.iter().zip(&responses) {
+            assert_eq!(resp.id(), req.id());
+            assert_eq!(first_a(resp), Some(Ipv4Addr::new(10, 0, 0, 7)));
+        }
+        assert_eq!(slow.queries(), 1);
+        let stats = DnsHandle::stats(&*blocker);
+        assert_eq!((stats.leader_queries, stats.coalesced_queries), (1, 9));
+    }
+
+    #[tokio::test]
+    async fn stampede_on_nxdomain_shares_the_exact_answer() {
+        let slow = MockUpstream::start_with_delay(Duration::from_millis(100), |req| {
+            Some(reply(req, ResponseCode::NXDomain))
+        })
+        .await;
+        let blocker = TestBlocker::new(slow.addr).start().await;
+
+        let requests: Vec<_> = (0..5).map(|_| Request::new("missing.nx.test.", RecordType::A)).collect();
+        let responses = futures_util::future::join_all(requests.iter().map(|req| req.send(&blocker))).await;
+        for (req, resp) in requests.iter().zip(&responses) {
+            assert_eq!(resp.id(), req.id());
+            assert_eq!(resp.response_code(), ResponseCode::NXDomain);
+        }
+        assert_eq!(slow.queries(), 1);
+        assert_eq!(DnsHandle::stats(&blocker).coalesced_queries, 4);
+    }
+
+    // Chaque issue passe par `respond` : une réponse au client, comptée et journalisée
+    #[tokio::test]
+    async fn every_query_gets_an_answer() {
+        let upstream = MockUpstream::start(|req| {
+            let name = req.queries()[0].name().to_ascii();
+            // Les noms "down" simulent une panne de l'upstream
+            (!name.starts_with("down.")).then(|| answer(req, vec![a_record(&name, [10, 0, 0, 1])]))
+        })
+        .await;
+        let mut config = TestBlocker::new(upstream.addr);
+        config.upstream_timeout = Duration::from_millis(100);
+        config.access.allowed_clients = vec!["192.168.1.0/24".parse().unwrap()];
+        config.access.rate_limit_qps = 2;
+        let blocker = config.start().await;
+        blocker.blocked.write().unwrap().insert("ads.paths.test");
+
+        let outsider = IpAddr::from([10, 0, 0, 1]);
+        let other = IpAddr::from([192, 168, 1, 51]);
+        let cases = [
+            (outsider, Request::new("www.paths.test.", RecordType::A), ResponseCode::Refused),
+            (CLIENT, Request::new("ads.paths.test.", RecordType::A), ResponseCode::NXDomain),
+            (CLIENT, Request::new("www.paths.test.", RecordType::ANY), ResponseCode::NoError),
+            // Troisième requête dans la seconde : au-delà du rate limit
+            (CLIENT, Request::new("www.paths.test.", RecordType::A), ResponseCode::Refused),
+            (other, Request::new("www.paths.test.", RecordType::A), ResponseCode::NoError),
+            (other, Request::new("down.paths.test.", RecordType::A), ResponseCode::ServFail),
+            (other, Request::empty(), ResponseCode::FormErr),
+        ];
+        for (client, req, code) in &cases {
+            let resp = req.send_from(&blocker, *client).await;
+            assert_eq!(resp.id(), req.id());
+            assert_eq!(resp.response_code(), *code, "{:?}", resp.queries());
+        }
+
+        let stats = DnsHandle::stats(&blocker);
+        assert_eq!(stats.total_queries, cases.len() as u64);
+        assert_eq!((stats.refused_queries, stats.rate_limited_queries), (2, 1));
+        assert_eq!((stats.servfail_queries, stats.formerr_queries), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn query_without_question_gets_formerr() {
+        let upstream = upstream().await;
+        let blocker = TestBlocker::new(upstream.addr).start().await;
+
+        let req = Request::empty();
+        let resp = req.send(&blocker).await;
+        assert_eq!(resp.id(), req.id());
+        assert_eq!(resp.response_code(), ResponseCode::FormErr);
+        assert_eq!(DnsHandle::stats(&blocker).formerr_queries, 1);
+    }
+
+    #[tokio::test]
+    async fn unreadable_query_is_dropped() {
+        let upstream = upstream().await;
+        let blocker = TestBlocker::new(upstream.addr).start().await;
+        assert!(blocker.handle_request(&[0x12], CLIENT).await.unwrap().is_none());
     }
 }
@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use hickory_proto::rr::RecordType;
+use rust_hole_api::{BlocklistInfo, LatencySnapshot, StatsSnapshot};
+use rust_hole_db::QueryStatus;
+
+// Durées en microsecondes, min/max/moyenne depuis le démarrage
+pub struct Latency {
+    count: AtomicU64,
+    total_us: AtomicU64,
+    min_us: AtomicU64,
+    max_us: AtomicU64,
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            total_us: AtomicU64::new(0),
+            min_us: AtomicU64::new(u64::MAX),
+            max_us: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Latency {
+    pub fn record(&self, elapsed: Duration) {
+        let us = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_us.fetch_add(us, Ordering::Relaxed);
+        self.min_us.fetch_min(us, Ordering::Relaxed);
+        self.max_us.fetch_max(us, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> LatencySnapshot {
+        let count = self.count.load(Ordering::Relaxed);
+        if count == 0 {
+            return LatencySnapshot::default();
+        }
+        let ms = |us: u64| us as f64 / 1000.0;
+
+        LatencySnapshot {
+            count,
+            min_ms: ms(self.min_us.load(Ordering::Relaxed)),
+            avg_ms: ms(self.total_us.load(Ordering::Relaxed)) / count as f64,
+            max_ms: ms(self.max_us.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+// Types comptés à part, les autres vont dans "other"
+const TRACKED_TYPES: [RecordType; 10] = [
+    RecordType::A,
+    RecordType::AAAA,
+    RecordType::HTTPS,
+    RecordType::CNAME,
+    RecordType::MX,
+    RecordType::TXT,
+    RecordType::PTR,
+    RecordType::SRV,
+    RecordType::NS,
+    RecordType::SOA,
+];
+
+#[derive(Default)]
+struct QueryTypes {
+    tracked: [AtomicU64; TRACKED_TYPES.len()],
+    other: AtomicU64,
+}
+
+impl QueryTypes {
+    fn record(&self, rtype: RecordType) {
+        let counter = match TRACKED_TYPES.iter().position(|t| *t == rtype) {
+            Some(i) => &self.tracked[i],
+            None => &self.other,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> BTreeMap<String, u64> {
+        let mut types: BTreeMap<String, u64> = TRACKED_TYPES
+            .iter()
+            .zip(&self.tracked)
+            .map(|(t, count)| (t.to_string(), count.load(Ordering::Relaxed)))
+            .collect();
+        types.insert("other".to_string(), self.other.load(Ordering::Relaxed));
+        types
+    }
+}
+
+// Compteurs depuis le démarrage
+#[derive(Default)]
+pub struct Stats {
+    total: AtomicU64,
+    blocked: AtomicU64,
+    cache_hits: AtomicU64,
+    negative_cache_hits: AtomicU64,
+    forwarded: AtomicU64,
+    local: AtomicU64,
+    servfail: AtomicU64,
+    refused: AtomicU64,
+    formerr: AtomicU64,
+    // Sous-ensemble des refused : requêtes au-delà du rate limit
+    rate_limited: AtomicU64,
+    // Sous-ensemble des servfail : réponses rejetées par la validation DNSSEC
+    dnssec_failures: AtomicU64,
+    // Cache manqué : requêtes parties vers l'upstream, et celles qui ont
+    // attendu la réponse d'une requête identique déjà en vol
+    leader_queries: AtomicU64,
+    coalesced_queries: AtomicU64,
+    query_types: QueryTypes,
+    // Temps de réponse de l'upstream, et du cache à part pour ne pas fausser la moyenne
+    pub upstream_latency: Latency,
+    pub cache_latency: Latency,
+}
+
+impl Stats {
+    pub fn record(&self, status: QueryStatus, rtype: RecordType) {
+        self.total.fetch_add(1, Ordering::Relaxed);
+        self.query_types.record(rtype);
+        let counter = match status {
+            QueryStatus::Allowed => &self.forwarded,
+            QueryStatus::Blocked => &self.blocked,
+            QueryStatus::Cached => &self.cache_hits,
+            QueryStatus::Local => &self.local,
+            QueryStatus::ServFail => &self.servfail,
+            QueryStatus::Refused => &self.refused,
+            QueryStatus::FormErr => &self.formerr,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Sous-ensemble des cache_hits servis depuis une réponse négative
+    pub fn record_negative_hit(&self) {
+        self.negative_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_rate_limited(&self) {
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_dnssec_failure(&self) {
+        self.dnssec_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_pending(&self, coalesced: bool) {
+        let counter = if coalesced {
+            &self.coalesced_queries
+        } else {
+            &self.leader_queries
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // `disabled_for` : durée restante si le blocage est suspendu
+    pub fn snapshot(&self, disabled_for: Option<Duration>, blocklist: BlocklistInfo) -> StatsSnapshot {
+        let total = self.total.load(Ordering::Relaxed);
+        let blocked = self.blocked.load(Ordering::Relaxed);
+        let percent_blocked = if total == 0 {
+            0.0
+        } else {
+            blocked as f64 * 100.0 / total as f64
+        };
+
+        StatsSnapshot {
+            total_queries: total,
+            blocked_queries: blocked,
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            negative_cache_hits: self.negative_cache_hits.load(Ordering::Relaxed),
+            forwarded_queries: self.forwarded.load(Ordering::Relaxed),
+            local_queries: self.local.load(Ordering::Relaxed),
+            servfail_queries: self.servfail.load(Ordering::Relaxed),
+            refused_queries: self.refused.load(Ordering::Relaxed),
+            formerr_queries: self.formerr.load(Ordering::Relaxed),
+            rate_limited_queries: self.rate_limited.load(Ordering::Relaxed),
+            dnssec_failures: self.dnssec_failures.load(Ordering::Relaxed),
+            leader_queries: self.leader_queries.load(Ordering::Relaxed),
+            coalesced_queries: self.coalesced_queries.load(Ordering::Relaxed),
+            percent_blocked,
+            query_types: self.query_types.snapshot(),
+            upstream_latency: self.upstream_latency.snapshot(),
+            cache_latency: self.cache_latency.snapshot(),
+            blocklist,
+            blocking_enabled: disabled_for.is_none(),
+            blocking_disabled_secs: disabled_for.map(|d| d.as_secs()),
+        }
+    }
+}
@@ -0,0 +1,221 @@
+// Outils partagés par les tests du moteur DNS
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use tokio::net::UdpSocket;
+
+use rust_hole_db::testing::test_db;
+
+use super::cache::CacheConfig;
+use super::query_log::{LogVerbosity, QueryLogger};
+use super::server::{
+    AccessConfig, BlockingConfig, BlockingMode, DnsBlocker, DEFAULT_BLOCKED_TTL, DEFAULT_SOA_MNAME,
+    DEFAULT_SOA_RNAME,
+};
+use super::upstream::{ConditionalForwarders, Protocol, ResolverStrategy, UpstreamConfig};
+
+pub const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 50));
+
+// Upstream UDP local : `answer` construit la réponse, `None` pour ne pas
+// répondre (upstream en panne)
+pub struct MockUpstream {
+    pub addr: SocketAddr,
+    queries: Arc<AtomicUsize>,
+}
+
+impl MockUpstream {
+    pub async fn start<F>(answer: F) -> Self
+    where
+        F: Fn(&Message) -> Option<Message> + Send + Sync + 'static,
+    {
+        Self::start_with_delay(Duration::ZERO, answer).await
+    }
+
+    // Chaque réponse part après `delay`, sans bloquer les requêtes suivantes
+    pub async fn start_with_delay<F>(delay: Duration, answer: F) -> Self
+    where
+        F: Fn(&Message) -> Option<Message> + Send + Sync + 'static,
+    {
+        Self::bind("127.0.0.1:0", delay, answer).await
+    }
+
+    // Sur la boucle locale IPv6
+    pub async fn start_v6<F>(answer: F) -> Self
+    where
+        F: Fn(&Message) -> Option<Message> + Send + Sync + 'static,
+    {
+        Self::bind("[::1]:0", Duration::ZERO, answer).await
+    }
+
+    async fn bind<F>(addr: &str, delay: Duration, answer: F) -> Self
+    where
+        F: Fn(&Message) -> Option<Message> + Send + Sync + 'static,
+    {
+        let socket = Arc::new(UdpSocket::bind(addr).await.unwrap());
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let answer = Arc::new(answer);
+        let counter = queries.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+                let Ok(req) = Message::from_vec(&buf[..len]) else {
+                    continue;
+                };
+                counter.fetch_add(1, Ordering::SeqCst);
+                let (socket, answer) = (socket.clone(), answer.clone());
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    if let Some(resp) = answer(&req) {
+                        let _ = socket.send_to(&resp.to_vec().unwrap(), from).await;
+                    }
+                });
+            }
+        });
+        Self { addr, queries }
+    }
+
+    // Requêtes reçues depuis le démarrage
+    pub fn queries(&self) -> usize {
+        self.queries.load(Ordering::SeqCst)
+    }
+}
+
+// Réponse vide à `req` avec ce code
+pub fn reply(req: &Message, code: ResponseCode) -> Message {
+    let mut resp = Message::new();
+    resp.set_id(req.id());
+    resp.set_message_type(MessageType::Response);
+    resp.set_op_code(req.op_code());
+    resp.set_recursion_desired(req.recursion_desired());
+    resp.set_recursion_available(true);
+    resp.add_queries(req.queries().to_vec());
+    resp.set_response_code(code);
+    resp
+}
+
+// Réponse NOERROR de `req` avec ces enregistrements
+pub fn answer(req: &Message, answers: Vec<Record>) -> Message {
+    let mut resp = reply(req, ResponseCode::NoError);
+    resp.add_answers(answers);
+    resp
+}
+
+pub fn record(name: &str, ttl: u32, rdata: RData) -> Record {
+    Record::from_rdata(Name::from_ascii(name).unwrap(), ttl, rdata)
+}
+
+// ================= Requêtes =================
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+// Requête d'un client, encodée comme elle arriverait sur le port 53
+pub struct Request {
+    msg: Message,
+}
+
+impl Request {
+    pub fn new(domain: &str, rtype: RecordType) -> Self {
+        let mut msg = Message::new();
+        msg.set_id(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        msg.set_message_type(MessageType::Query);
+        msg.set_op_code(OpCode::Query);
+        msg.set_recursion_desired(true);
+        msg.add_query(Query::query(Name::from_ascii(domain).unwrap(), rtype));
+        Self { msg }
+    }
+
+    // Requête sans question, pour le FORMERR
+    pub fn empty() -> Self {
+        let mut msg = Message::new();
+        msg.set_id(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        Self { msg }
+    }
+
+    pub fn id(&self) -> u16 {
+        self.msg.id()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.msg.to_vec().unwrap()
+    }
+
+    // Envoie la requête à `blocker` et décode sa réponse
+    pub async fn send(&self, blocker: &DnsBlocker) -> Message {
+        self.send_from(blocker, CLIENT).await
+    }
+
+    pub async fn send_from(&self, blocker: &DnsBlocker, client: IpAddr) -> Message {
+        let bytes = blocker
+            .handle_request(&self.to_bytes(), client)
+            .await
+            .unwrap()
+            .expect("toute requête lisible reçoit une réponse");
+        Message::from_vec(&bytes).unwrap()
+    }
+}
+
+// ================= Serveur =================
+// Réglages d'un `DnsBlocker` de test, à modifier avant `start`
+pub struct TestBlocker {
+    pub upstreams: Vec<SocketAddr>,
+    pub upstream_timeout: Duration,
+    pub strategy: ResolverStrategy,
+    pub blocking: BlockingConfig,
+    pub cache: CacheConfig,
+    pub access: AccessConfig,
+    pub ecs_passthrough: bool,
+}
+
+impl TestBlocker {
+    pub fn new(upstream: SocketAddr) -> Self {
+        Self {
+            upstreams: vec![upstream],
+            upstream_timeout: Duration::from_millis(300),
+            strategy: ResolverStrategy::Failover,
+            blocking: BlockingConfig {
+                mode: BlockingMode::NxDomain,
+                ttl: DEFAULT_BLOCKED_TTL,
+                soa_mname: Name::from_ascii(DEFAULT_SOA_MNAME).unwrap(),
+                soa_rname: Name::from_ascii(DEFAULT_SOA_RNAME).unwrap(),
+                txt_reason: false,
+            },
+            cache: CacheConfig::default(),
+            access: AccessConfig {
+                rate_limit_qps: 0,
+                allowed_clients: Vec::new(),
+                forward_any: false,
+            },
+            ecs_passthrough: false,
+        }
+    }
+
+    pub async fn start(self) -> DnsBlocker {
+        test_db().await;
+        let upstream = UpstreamConfig::new(
+            self.upstreams,
+            Protocol::Udp,
+            None,
+            self.upstream_timeout,
+            1,
+            self.strategy,
+            false,
+        )
+        .unwrap();
+        let (query_log, _writer) = QueryLogger::start(LogVerbosity::Off);
+        DnsBlocker::new(
+            self.blocking,
+            upstream,
+            ConditionalForwarders::default(),
+            self.ecs_passthrough,
+            query_log,
+            self.cache,
+            self.access,
+        )
+        .await
+        .unwrap()
+    }
+}
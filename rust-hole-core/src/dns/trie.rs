@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+// ================= Domain trie =================
+// Labels rangés de droite à gauche (com -> example -> ads) : la recherche
+// d'un nom coûte au plus un pas par label, quelle que soit la taille de la liste
+#[derive(Default)]
+pub struct DomainTrie {
+    root: Node,
+    len: usize,
+}
+
+#[derive(Default)]
+struct Node {
+    // Une entrée se termine ici : ce domaine et tous ses sous-domaines correspondent
+    terminal: bool,
+    // Source de la blocklist qui a ajouté l'entrée, la première si plusieurs
+    source: Option<i64>,
+    children: HashMap<Box<str>, Node>,
+}
+
+impl DomainTrie {
+    // Retourne `false` si le domaine était déjà présent. Normalisé comme les
+    // requêtes (minuscules, sans point final) : les lignes insérées avant
+    // normalize_domain peuvent encore contenir l'un ou l'autre
+    pub fn insert(&mut self, domain: &str) -> bool {
+        self.insert_from(domain, None)
+    }
+
+    // Comme `insert`, en retenant la source de l'entrée
+    pub fn insert_from(&mut self, domain: &str, source: Option<i64>) -> bool {
+        let mut node = &mut self.root;
+        for label in domain.trim_end_matches('.').rsplit('.') {
+            node = node.children.entry(label.to_ascii_lowercase().into()).or_default();
+        }
+        if node.terminal {
+            return false;
+        }
+        node.terminal = true;
+        node.source = source;
+        self.len += 1;
+        true
+    }
+
+    // Retourne `false` si le domaine n'était pas une entrée. Ses sous-domaines
+    // présents comme entrées à part restent dans le trie
+    pub fn remove(&mut self, domain: &str) -> bool {
+        let labels: Vec<String> = domain
+            .trim_end_matches('.')
+            .rsplit('.')
+            .map(|label| label.to_ascii_lowercase())
+            .collect();
+        if !self.root.remove(&labels) {
+            return false;
+        }
+        self.len -= 1;
+        true
+    }
+
+    // Vrai si `name` ou l'un de ses domaines parents est dans le trie
+    pub fn matches(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+
+    // Entrée qui couvre `name` : lui-même ou le plus court de ses parents présent
+    pub fn find<'a>(&self, name: &'a str) -> Option<&'a str> {
+        self.find_with_source(name).map(|(entry, _)| entry)
+    }
+
+    // Comme `find`, avec la source de l'entrée
+    pub fn find_with_source<'a>(&self, name: &'a str) -> Option<(&'a str, Option<i64>)> {
+        let mut node = &self.root;
+        let mut start = name.len();
+        for label in name.rsplit('.') {
+            start -= label.len();
+            match node.children.get(label) {
+                Some(child) if child.terminal => return Some((&name[start..], child.source)),
+                Some(child) => node = child,
+                None => return None,
+            }
+            // Le point qui précède le label
+            start = start.saturating_sub(1);
+        }
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Node {
+    // Les nœuds devenus inutiles (ni entrée ni enfant) sont retirés au passage
+    fn remove(&mut self, labels: &[String]) -> bool {
+        let Some((label, rest)) = labels.split_first() else {
+            let was_terminal = self.terminal;
+            self.terminal = false;
+            self.source = None;
+            return was_terminal;
+        };
+        let Some(child) = self.children.get_mut(label.as_str()) else {
+            return false;
+        };
+        let removed = child.remove(rest);
+        if !child.terminal && child.children.is_empty() {
+            self.children.remove(label.as_str());
+        }
+        removed
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for DomainTrie {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+        let mut trie = Self::default();
+        for domain in iter {
+            trie.insert(domain.as_ref());
+        }
+        trie
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_on_label_boundaries() {
+        let trie: DomainTrie = ["example.com"].into_iter().collect();
+        assert!(trie.matches("example.com"));
+        assert!(trie.matches("www.example.com"));
+        assert!(trie.matches("a.b.example.com"));
+        assert!(!trie.matches("notexample.com"));
+        assert!(!trie.matches("example.com.evil.com"));
+        assert!(!trie.matches("com"));
+    }
+
+    #[test]
+    fn find_returns_the_shortest_covering_entry() {
+        let mut trie = DomainTrie::default();
+        trie.insert_from("ads.example.com", Some(2));
+        trie.insert_from("example.com", Some(1));
+        assert_eq!(trie.find_with_source("x.ads.example.com"), Some(("example.com", Some(1))));
+        assert_eq!(trie.find("other.org"), None);
+    }
+
+    #[test]
+    fn entries_are_normalized_and_deduplicated() {
+        let mut trie = DomainTrie::default();
+        assert!(trie.insert_from("Ads.Example.COM.", Some(1)));
+        assert!(!trie.insert_from("ads.example.com", Some(2)));
+        assert_eq!(trie.len(), 1);
+        // La première source est conservée
+        assert_eq!(trie.find_with_source("ads.example.com"), Some(("ads.example.com", Some(1))));
+    }
+
+    #[test]
+    fn remove_keeps_other_entries() {
+        let mut trie: DomainTrie = ["example.com", "ads.example.com"].into_iter().collect();
+        assert!(trie.remove("example.com"));
+        assert!(!trie.remove("example.com"));
+        assert_eq!(trie.len(), 1);
+        assert!(!trie.matches("www.example.com"));
+        assert!(trie.matches("x.ads.example.com"));
+    }
+}
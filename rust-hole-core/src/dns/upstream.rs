@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::future::select_ok;
+
+use hickory_proto::op::{Edns, Message, Query, ResponseCode};
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use hickory_proto::rr::{Name, RecordType};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+use tokio_native_tls::{native_tls, TlsConnector};
+use tracing::warn;
+
+pub const DEFAULT_UPSTREAM: &str = "8.8.8.8";
+pub const DEFAULT_UPSTREAM_TIMEOUT_SECS: u64 = 2;
+pub const DEFAULT_UPSTREAM_ATTEMPTS: u32 = 1;
+// Nom résolu au démarrage pour vérifier la configuration
+pub const PROBE_DOMAIN: &str = "dns.google.";
+
+// Identifiants des requêtes émises par le serveur lui-même
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Udp,
+    Tcp,
+    // DNS-over-TLS (RFC 7858)
+    Tls,
+}
+
+impl Protocol {
+    pub fn default_port(self) -> u16 {
+        match self {
+            Protocol::Udp | Protocol::Tcp => 53,
+            Protocol::Tls => 853,
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "udp" => Ok(Self::Udp),
+            "tcp" => Ok(Self::Tcp),
+            "tls" | "dot" => Ok(Self::Tls),
+            other => Err(anyhow::anyhow!("Protocole upstream inconnu: {}", other)),
+        }
+    }
+}
+
+// Répartition des requêtes entre les upstreams
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResolverStrategy {
+    // Toujours dans l'ordre, le suivant seulement en cas d'échec
+    #[default]
+    Failover,
+    // Le premier serveur essayé change à chaque requête
+    RoundRobin,
+    // Tous interrogés en parallèle, la première réponse gagne. Un ServFail ou
+    // un Refused ne gagne que si aucun autre serveur n'a répondu
+    Fastest,
+}
+
+impl FromStr for ResolverStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "failover" | "sequential" => Ok(Self::Failover),
+            "round-robin" | "roundrobin" => Ok(Self::RoundRobin),
+            "fastest" | "race" => Ok(Self::Fastest),
+            other => Err(anyhow::anyhow!("Stratégie upstream inconnue: {}", other)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UpstreamConfig {
+    pub servers: Vec<SocketAddr>,
+    pub protocol: Protocol,
+    // Nom vérifié dans le certificat, requis en Tls (ex: cloudflare-dns.com)
+    pub tls_dns_name: Option<String>,
+    // Délai par serveur et nombre de passes sur la liste avant le ServFail
+    pub timeout: Duration,
+    pub attempts: u32,
+    pub strategy: ResolverStrategy,
+    // Validation DNSSEC exigée de l'upstream, qui doit être un résolveur validant
+    pub dnssec: bool,
+    // Position du round-robin, partagée entre les requêtes
+    next: Arc<AtomicUsize>,
+}
+
+impl UpstreamConfig {
+    pub fn new(
+        servers: Vec<SocketAddr>,
+        protocol: Protocol,
+        tls_dns_name: Option<String>,
+        timeout: Duration,
+        attempts: u32,
+        strategy: ResolverStrategy,
+        dnssec: bool,
+    ) -> anyhow::Result<Self> {
+        if servers.is_empty() {
+            return Err(anyhow::anyhow!("Aucun upstream configuré"));
+        }
+        if protocol == Protocol::Tls && tls_dns_name.is_none() {
+            return Err(anyhow::anyhow!("Un tls_dns_name est requis pour un upstream Tls"));
+        }
+        if timeout.is_zero() {
+            return Err(anyhow::anyhow!("Le délai upstream doit être supérieur à zéro"));
+        }
+        Ok(Self {
+            servers,
+            protocol,
+            tls_dns_name,
+            timeout,
+            attempts: attempts.max(1),
+            strategy,
+            dnssec,
+            next: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+}
+
+// "8.8.8.8:53,1.1.1.1,[2606:4700:4700::1111]:53,2620:fe::fe" : `default_port`
+// est utilisé quand le port est omis
+pub fn parse_upstreams(value: &str, default_port: u16) -> anyhow::Result<Vec<SocketAddr>> {
+    let upstreams = value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            s.parse::<SocketAddr>()
+                .or_else(|_| s.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, default_port)))
+                .map_err(|_| anyhow::anyhow!("Upstream invalide: {}", s))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    if upstreams.is_empty() {
+        return Err(anyhow::anyhow!("Aucun upstream configuré"));
+    }
+    Ok(upstreams)
+}
+
+// ================= Conditional forwarding =================
+// Suffixe de domaine -> résolveur dédié, interrogé en UDP (ex: home.lan -> routeur)
+#[derive(Clone, Debug, Default)]
+pub struct ConditionalForwarders {
+    rules: HashMap<String, UpstreamConfig>,
+}
+
+impl ConditionalForwarders {
+    // "home.lan=192.168.1.1,corp.example=10.0.0.53:5353"
+    pub fn parse(value: &str, timeout: Duration, attempts: u32) -> anyhow::Result<Self> {
+        let mut rules = HashMap::new();
+        for rule in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (suffix, resolver) = rule
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Règle de transfert invalide (suffixe=ip attendu): {}", rule))?;
+            let suffix = suffix.trim().trim_matches('.').to_ascii_lowercase();
+            if suffix.is_empty() {
+                return Err(anyhow::anyhow!("Suffixe vide dans la règle: {}", rule));
+            }
+            let servers = parse_upstreams(resolver, Protocol::Udp.default_port())?;
+            // Les résolveurs du réseau local valident rarement, pas de DNSSEC exigé
+            let config = UpstreamConfig::new(
+                servers,
+                Protocol::Udp,
+                None,
+                timeout,
+                attempts,
+                ResolverStrategy::Failover,
+                false,
+            )?;
+            rules.insert(suffix, config);
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn suffixes(&self) -> Vec<&str> {
+        self.rules.keys().map(String::as_str).collect()
+    }
+
+    // Règle la plus spécifique : on retire les labels de gauche un à un
+    pub fn select(&self, name: &str) -> Option<&UpstreamConfig> {
+        let name = name.to_ascii_lowercase();
+        let mut current = name.as_str();
+        loop {
+            if let Some(config) = self.rules.get(current) {
+                return Some(config);
+            }
+            current = current.split_once('.')?.1;
+        }
+    }
+}
+
+// Interroge les upstreams selon la stratégie jusqu'à obtenir une réponse,
+// en repassant sur la liste `attempts` fois
+pub async fn forward(
+    config: &UpstreamConfig,
+    req_bytes: &[u8],
+    id: u16,
+) -> anyhow::Result<Message> {
+    for attempt in 1..=config.attempts {
+        if config.strategy == ResolverStrategy::Fastest {
+            // Un upstream en panne qui répond ServFail tout de suite ne doit
+            // pas gagner la course contre une vraie réponse
+            let rejected = Mutex::new(None);
+            let racers = config.servers.iter().map(|upstream| {
+                let rejected = &rejected;
+                Box::pin(async move {
+                    let msg = forward_once(config, *upstream, req_bytes, id).await?;
+                    let code = msg.response_code();
+                    if matches!(code, ResponseCode::ServFail | ResponseCode::Refused) {
+                        *rejected.lock().unwrap() = Some(msg);
+                        return Err(anyhow::anyhow!("{} a répondu {}", upstream, code));
+                    }
+                    Ok(msg)
+                })
+            });
+            match select_ok(racers).await {
+                Ok((msg, _)) => return Ok(msg),
+                Err(e) => warn!(attempt, error = format!("{:#}", e), "Aucun upstream n'a répondu"),
+            }
+            // Tous en échec : l'erreur d'un upstream reste la réponse, comme
+            // en failover (ServFail DNSSEC notamment)
+            if let Some(msg) = rejected.into_inner().unwrap() {
+                return Ok(msg);
+            }
+            continue;
+        }
+
+        let start = match config.strategy {
+            ResolverStrategy::RoundRobin => config.next.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+        let servers = config.servers.iter().cycle().skip(start % config.servers.len());
+        for upstream in servers.take(config.servers.len()) {
+            match forward_once(config, *upstream, req_bytes, id).await {
+                Ok(msg) => return Ok(msg),
+                Err(e) => warn!(%upstream, attempt, error = format!("{:#}", e), "Upstream en échec"),
+            }
+        }
+    }
+    Err(anyhow::anyhow!("Aucun upstream n'a répondu"))
+}
+
+// ================= DNSSEC =================
+// La validation est faite par l'upstream : rust-hole s'assure qu'elle ne
+// peut pas être contournée et reconnaît ses échecs
+
+// Extended DNS Errors (RFC 8914) liées à DNSSEC : algorithme ou digest non
+// supporté, indéterminé, bogus, signature expirée ou pas encore valide,
+// DNSKEY, RRSIG ou NSEC manquants, bit Zone Key absent
+const EDE_OPTION_CODE: u16 = 15;
+const EDE_DNSSEC_CODES: [u16; 10] = [1, 2, 5, 6, 7, 8, 9, 10, 11, 12];
+const VALIDATION_EDNS_PAYLOAD: u16 = 1232;
+
+// Requête du client réécrite pour un upstream validant : bit CD retiré (le
+// client ne peut pas désactiver la validation) et EDNS ajouté au besoin, sans
+// quoi l'upstream ne joint pas l'erreur étendue à son ServFail
+pub fn validating_request(req_bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut msg = Message::from_vec(req_bytes)?;
+    msg.set_checking_disabled(false);
+    if msg.extensions().is_none() {
+        let mut edns = Edns::new();
+        edns.set_max_payload(VALIDATION_EDNS_PAYLOAD);
+        msg.set_edns(edns);
+    }
+    Ok(msg.to_vec()?)
+}
+
+// ServFail de l'upstream dû à un échec de validation, et non à une panne
+pub fn dnssec_failed(resp: &Message) -> bool {
+    if resp.response_code() != ResponseCode::ServFail {
+        return false;
+    }
+    let Some(edns) = resp.extensions() else {
+        return false;
+    };
+    match edns.options().get(EdnsCode::Unknown(EDE_OPTION_CODE)) {
+        Some(EdnsOption::Unknown(_, data)) if data.len() >= 2 => {
+            EDE_DNSSEC_CODES.contains(&u16::from_be_bytes([data[0], data[1]]))
+        }
+        _ => false,
+    }
+}
+
+// Requête émise par le serveur (sonde, résolution inverse), hors requête client
+pub async fn query(config: &UpstreamConfig, name: Name, rtype: RecordType) -> anyhow::Result<Message> {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let mut msg = Message::new();
+    msg.set_id(id);
+    msg.set_recursion_desired(true);
+    msg.add_query(Query::query(name, rtype));
+    forward(config, &msg.to_vec()?, id).await
+}
+
+async fn forward_once(
+    config: &UpstreamConfig,
+    upstream: SocketAddr,
+    req_bytes: &[u8],
+    id: u16,
+) -> anyhow::Result<Message> {
+    timeout(config.timeout, forward_to(config, upstream, req_bytes, id))
+        .await
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("Pas de réponse après {:?}", config.timeout)))
+}
+
+async fn forward_to(
+    config: &UpstreamConfig,
+    upstream: SocketAddr,
+    req_bytes: &[u8],
+    id: u16,
+) -> anyhow::Result<Message> {
+    match config.protocol {
+        Protocol::Udp => {
+            let msg = forward_udp(upstream, req_bytes, id).await?;
+            // Réponse tronquée : on repasse en TCP pour obtenir la réponse complète
+            if msg.truncated() {
+                let mut stream = TcpStream::connect(upstream).await?;
+                return forward_stream(&mut stream, req_bytes).await;
+            }
+            Ok(msg)
+        }
+        Protocol::Tcp => {
+            let mut stream = TcpStream::connect(upstream).await?;
+            forward_stream(&mut stream, req_bytes).await
+        }
+        Protocol::Tls => {
+            let tls_dns_name = config
+                .tls_dns_name
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("tls_dns_name manquant"))?;
+            let connector = TlsConnector::from(native_tls::TlsConnector::new()?);
+            let tcp = TcpStream::connect(upstream).await?;
+            let mut stream = connector.connect(tls_dns_name, tcp).await?;
+            forward_stream(&mut stream, req_bytes).await
+        }
+    }
+}
+
+async fn forward_udp(upstream: SocketAddr, req_bytes: &[u8], id: u16) -> anyhow::Result<Message> {
+    // Socket éphémère par requête : les requêtes concurrentes ne se volent pas leurs réponses
+    // Même famille que l'upstream : une socket IPv4 ne peut pas joindre 2606:4700:4700::1111
+    let local: SocketAddr = match upstream {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.send_to(req_bytes, upstream).await?;
+
+    let mut buf = [0u8; 4096];
+    loop {
+        let (len, from) = socket.recv_from(&mut buf).await?;
+        // Ignore les réponses tardives d'un autre upstream ou d'une autre requête
+        if from != upstream {
+            continue;
+        }
+        let msg = Message::from_vec(&buf[..len])?;
+        if msg.id() == id {
+            return Ok(msg);
+        }
+    }
+}
+
+// TCP et TLS : message préfixé par sa longueur sur 2 octets (RFC 1035 §4.2.2)
+async fn forward_stream<S>(stream: &mut S, req_bytes: &[u8]) -> anyhow::Result<Message>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let len = u16::try_from(req_bytes.len())?;
+    stream.write_all(&len.to_be_bytes()).await?;
+    stream.write_all(req_bytes).await?;
+    stream.flush().await?;
+
+    let resp_len = stream.read_u16().await? as usize;
+    let mut resp_buf = vec![0u8; resp_len];
+    stream.read_exact(&mut resp_buf).await?;
+
+    Ok(Message::from_vec(&resp_buf)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::testing::{reply, MockUpstream};
+
+    fn fastest(servers: Vec<SocketAddr>) -> UpstreamConfig {
+        UpstreamConfig::new(
+            servers,
+            Protocol::Udp,
+            None,
+            Duration::from_millis(500),
+            1,
+            ResolverStrategy::Fastest,
+            false,
+        )
+        .unwrap()
+    }
+
+    async fn ask(config: &UpstreamConfig) -> anyhow::Result<Message> {
+        query(config, Name::from_ascii("example.com.").unwrap(), RecordType::A).await
+    }
+
+    #[tokio::test]
+    async fn fastest_skips_servfail_and_refused() {
+        let broken = MockUpstream::start(|req| Some(reply(req, ResponseCode::ServFail))).await;
+        let refusing = MockUpstream::start(|req| Some(reply(req, ResponseCode::Refused))).await;
+        let slow = MockUpstream::start_with_delay(Duration::from_millis(50), |req| {
+            Some(reply(req, ResponseCode::NoError))
+        })
+        .await;
+
+        let resp = ask(&fastest(vec![broken.addr, refusing.addr, slow.addr])).await.unwrap();
+        assert_eq!(resp.response_code(), ResponseCode::NoError);
+    }
+
+    #[tokio::test]
+    async fn fastest_returns_servfail_when_every_upstream_fails() {
+        let broken = MockUpstream::start(|req| Some(reply(req, ResponseCode::ServFail))).await;
+        let silent = MockUpstream::start(|_| None).await;
+
+        let resp = ask(&fastest(vec![broken.addr, silent.addr])).await.unwrap();
+        assert_eq!(resp.response_code(), ResponseCode::ServFail);
+    }
+
+    #[tokio::test]
+    async fn fastest_errors_without_any_answer() {
+        let silent = MockUpstream::start(|_| None).await;
+        assert!(ask(&fastest(vec![silent.addr])).await.is_err());
+        assert_eq!(silent.queries(), 1);
+    }
+
+    #[tokio::test]
+    async fn failover_tries_next_server_on_timeout() {
+        let silent = MockUpstream::start(|_| None).await;
+        let working = MockUpstream::start(|req| Some(reply(req, ResponseCode::NoError))).await;
+        let config = UpstreamConfig::new(
+            vec![silent.addr, working.addr],
+            Protocol::Udp,
+            None,
+            Duration::from_millis(100),
+            1,
+            ResolverStrategy::Failover,
+            false,
+        )
+        .unwrap();
+
+        let resp = ask(&config).await.unwrap();
+        assert_eq!(resp.response_code(), ResponseCode::NoError);
+        assert_eq!((silent.queries(), working.queries()), (1, 1));
+    }
+
+    #[test]
+    fn empty_server_list_is_rejected() {
+        // Le round-robin ferait `start % 0`
+        let config = UpstreamConfig::new(
+            Vec::new(),
+            Protocol::Udp,
+            None,
+            Duration::from_millis(500),
+            1,
+            ResolverStrategy::RoundRobin,
+            false,
+        );
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn parses_ipv6_upstreams() {
+        let upstreams = parse_upstreams("1.1.1.1, [2606:4700:4700::1111]:5353,2620:fe::fe", 53).unwrap();
+        assert_eq!(
+            upstreams,
+            vec![
+                "1.1.1.1:53".parse().unwrap(),
+                "[2606:4700:4700::1111]:5353".parse().unwrap(),
+                "[2620:fe::fe]:53".parse().unwrap(),
+            ]
+        );
+        assert!(parse_upstreams("2606:4700::zz", 53).is_err());
+    }
+
+    #[tokio::test]
+    async fn forwards_to_an_ipv6_upstream() {
+        let upstream = MockUpstream::start_v6(|req| Some(reply(req, ResponseCode::NoError))).await;
+        assert!(upstream.addr.is_ipv6());
+        let config = UpstreamConfig::new(
+            vec![upstream.addr],
+            Protocol::Udp,
+            None,
+            Duration::from_millis(500),
+            1,
+            ResolverStrategy::Failover,
+            false,
+        )
+        .unwrap();
+
+        let resp = ask(&config).await.unwrap();
+        assert_eq!(resp.response_code(), ResponseCode::NoError);
+        assert_eq!(upstream.queries(), 1);
+    }
+}
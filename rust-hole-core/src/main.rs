@@ -1,49 +1,197 @@
+mod config;
 mod dns;
 
-use dns::server::run_dns;
-use rust_hole_db::init_db;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use config::{Config, DatabaseSection};
+use dns::cache::CacheConfig;
+use dns::query_log::QueryLogger;
+use dns::server::{run_dns, watch_hosts_file, AccessConfig, BlockingConfig, DnsBlocker};
+use dns::upstream::{parse_upstreams, ConditionalForwarders, UpstreamConfig};
+use rust_hole_db::{init_db, prune_query_log, DbConfig};
 
 use warp::{Filter, http::Response};
 use rust_embed::RustEmbed;
 use mime_guess::from_path;
-use rust_hole_api::run_api;
+use rust_hole_api::frontend::StaticFile;
+use rust_hole_api::sources::{http_client, refresh_all_sources, HttpClient};
+use rust_hole_api::{run_api, ApiConfig, BuildInfo};
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
 
 #[derive(RustEmbed)]
 #[folder = "../rust-hole-dashboard/dist"]
 struct Frontend;
 
-async fn serve_frontend() {
-    let routes = warp::path::full().map(|path: warp::path::FullPath| {
-        let path = path.as_str().trim_start_matches('/');
-
-        let path = if path.is_empty() {
-            "index.html"
-        } else {
-            path
-        };
-
-        match Frontend::get(path) {
-            Some(file) => {
-                let mime = from_path(path).first_or_octet_stream();
-                Response::builder()
-                    .header("content-type", mime.as_ref())
-                    .body(file.data.to_vec())
+const QUERY_LOG_PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+// Chemins qui ne sont jamais des routes du dashboard : un fichier absent de
+// ces dossiers est une vraie 404, pas la page d'accueil
+const NON_SPA_PREFIXES: [&str; 2] = ["api", "assets"];
+
+// Route React Router : dernier segment sans extension, hors assets et API
+fn is_client_route(path: &str) -> bool {
+    let first = path.split('/').next().unwrap_or_default();
+    let last = path.rsplit('/').next().unwrap_or_default();
+    !NON_SPA_PREFIXES.contains(&first) && std::path::Path::new(last).extension().is_none()
+}
+
+// Un fichier dont le nom contient son empreinte ne change jamais : le
+// navigateur le garde un an. index.html, qui référence les empreintes
+// courantes, est revalidé à chaque chargement
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const NO_CACHE: &str = "no-cache";
+// Vite place tous les fichiers à empreinte dans ce dossier
+const FINGERPRINTED_DIR: &str = "assets/";
+
+fn cache_control(path: &str) -> &'static str {
+    if path.starts_with(FINGERPRINTED_DIR) || has_content_hash(path) {
+        IMMUTABLE
+    } else {
+        NO_CACHE
+    }
+}
+
+// index-DiwrgTza.js (Vite), main.3f2a1b9c.js (webpack) : dernier segment du
+// nom d'au moins 8 caractères, avec chiffres ou majuscules pour ne pas
+// confondre un mot (my-component.js) avec une empreinte
+fn has_content_hash(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let Some(stem) = std::path::Path::new(name).file_stem().and_then(|s| s.to_str()) else {
+        return false;
+    };
+    let Some((_, hash)) = stem.rsplit_once(['-', '.']) else {
+        return false;
+    };
+    hash.len() >= 8
+        && hash.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && hash.chars().any(|c| c.is_ascii_digit() || c.is_ascii_uppercase())
+}
+
+// Passe si l'Accept-Encoding de la requête contient `encoding` sans q=0
+fn accepts_encoding(encoding: &'static str) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("accept-encoding")
+        .and_then(move |header: Option<String>| async move {
+            let accepted = header.as_deref().unwrap_or_default().split(',').any(|item| {
+                let mut parts = item.split(';').map(str::trim);
+                let name = parts.next().unwrap_or_default();
+                let refused = parts.any(|p| p.strip_prefix("q=").is_some_and(|q| q.parse() == Ok(0.0)));
+                name.eq_ignore_ascii_case(encoding) && !refused
+            });
+            if accepted {
+                Ok(())
+            } else {
+                Err(warp::reject())
             }
-            None => {
-                // SPA fallback (React Router)
-                let index = Frontend::get("index.html").unwrap();
-                Response::builder()
-                    .header("content-type", "text/html")
-                    .body(index.data.into())
+        })
+        .untuple_one()
+}
+
+// Fichier du dashboard pour `path` (sans le `/` initial), partagé entre le
+// serveur frontend et le mode unifié
+fn frontend_file(path: &str) -> StaticFile {
+    let path = if path.is_empty() {
+        "index.html"
+    } else {
+        path
+    };
+
+    match Frontend::get(path) {
+        Some(file) => StaticFile {
+            status: 200,
+            content_type: from_path(path).first_or_octet_stream().to_string(),
+            cache_control: Some(cache_control(path)),
+            body: file.data,
+        },
+        None if is_client_route(path) => {
+            // SPA fallback (React Router)
+            let index = Frontend::get("index.html").unwrap();
+            StaticFile {
+                status: 200,
+                content_type: "text/html".to_string(),
+                cache_control: Some(NO_CACHE),
+                body: index.data,
             }
         }
+        None => StaticFile {
+            status: 404,
+            content_type: "text/plain".to_string(),
+            cache_control: None,
+            body: b"Not Found".as_slice().into(),
+        },
+    }
+}
+
+async fn serve_frontend(addr: SocketAddr) {
+    let routes = warp::path::full().map(|path: warp::path::FullPath| {
+        let file = frontend_file(path.as_str().trim_start_matches('/'));
+        let mut resp = Response::builder()
+            .status(file.status)
+            .header("content-type", file.content_type);
+        if let Some(cache_control) = file.cache_control {
+            resp = resp.header("cache-control", cache_control);
+        }
+        resp.body(file.body.into_owned())
     });
 
+    // warp compresse sans regarder l'Accept-Encoding : chaque variante n'est
+    // servie qu'aux clients qui l'annoncent, les autres reçoivent le fichier brut
+    let brotli = accepts_encoding("br")
+        .and(routes)
+        .with(warp::compression::brotli());
+    let gzip = accepts_encoding("gzip")
+        .and(routes)
+        .with(warp::compression::gzip());
+    let routes = brotli.or(gzip).or(routes);
+
+    info!(%addr, "Serveur frontend démarré");
     warp::serve(routes)
-        .run(([0, 0, 0, 0], 3000))
+        .run(addr)
         .await;
 }
 
+// Variables fournies par build.rs
+fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: Some(env!("RUSTHOLE_GIT_COMMIT")).filter(|c| !c.is_empty()),
+        built_at: env!("RUSTHOLE_BUILD_TIME").parse().unwrap_or_default(),
+    }
+}
+
+// Met à jour les listes distantes puis recharge la blocklist du serveur DNS
+async fn refresh_sources(blocker: Arc<DnsBlocker>, http: HttpClient, every: Duration) {
+    let mut ticker = tokio::time::interval(every);
+
+    loop {
+        ticker.tick().await;
+        match refresh_all_sources(&http).await {
+            Ok(0) => {}
+            Ok(_) => {
+                if let Err(e) = blocker.reload_blocklist().await {
+                    error!("Erreur lors du rechargement de la blocklist: {:#}", e);
+                }
+            }
+            Err(e) => error!("Erreur lors de la mise à jour des sources: {:#}", e),
+        }
+    }
+}
+
+// Supprime régulièrement les entrées du journal plus anciennes que `retention`
+async fn prune_query_log_task(retention: Duration) {
+    let mut ticker = tokio::time::interval(QUERY_LOG_PRUNE_INTERVAL);
+
+    loop {
+        ticker.tick().await;
+        match prune_query_log(retention).await {
+            Ok(pruned) => info!(pruned, "Journal des requêtes élagué"),
+            Err(e) => error!("Erreur lors de l'élagage du journal: {:#}", e),
+        }
+    }
+}
+
 fn ascii_art() {
     println!(
 r#"
@@ -64,36 +212,201 @@ r#"
     );
 }
 
+// database.url (DATABASE_URL), sinon un fichier SQLite dans le dossier de
+// données (database.data_dir, ou ~/.local/share/rust-hole sous Linux)
+fn database_url(config: &DatabaseSection) -> anyhow::Result<String> {
+    if let Some(url) = &config.url {
+        return Ok(url.clone());
+    }
+
+    let data_dir = match &config.data_dir {
+        Some(dir) => dir.clone(),
+        None => dirs::data_dir()
+            .ok_or_else(|| anyhow::anyhow!("Dossier de données introuvable, définir RUSTHOLE_DATA_DIR ou DATABASE_URL"))?
+            .join("rust-hole"),
+    };
+    std::fs::create_dir_all(&data_dir).map_err(|e| {
+        anyhow::anyhow!("Impossible de créer le dossier de données {}: {}", data_dir.display(), e)
+    })?;
+    let path = std::path::absolute(data_dir.join("rusthole.db"))?;
+
+    // mode=rwc : le fichier est créé s'il n'existe pas encore
+    Ok(format!("sqlite://{}?mode=rwc", path.display()))
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let started = Instant::now();
     ascii_art();
 
-    init_db().await?;
+    // RUST_LOG=debug pour voir chaque requête DNS ; sqlx journalise chaque
+    // requête SQL en info, on le limite aux avertissements par défaut
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info,sqlx=warn")),
+        )
+        .init();
+
+    let config = Config::load()?;
+
+    let db = DbConfig {
+        url: database_url(&config.database)?,
+        max_connections: config.database.max_connections,
+        min_connections: config.database.min_connections,
+        connect_timeout: Duration::from_secs(config.database.connect_timeout_secs),
+        busy_timeout: Duration::from_secs(config.database.busy_timeout_secs),
+    };
+    init_db(&db).await?;
+
+    let dns_addr = config.dns.addr;
+    let dns_fallback_port = config.dns.fallback_port;
+    let http_addr = config.http.addr;
+    // Mode unifié : un seul serveur HTTP, sur l'adresse du dashboard
+    let unified = config.http.unified;
+    let http = http_client()?;
+    let api = ApiConfig {
+        addr: if unified { http_addr } else { config.api.addr },
+        cors_origins: config.api.cors_origins,
+        api_key: config.api.api_key.filter(|k| !k.is_empty()),
+        public_reads: config.api.public_reads,
+        frontend: unified.then_some(frontend_file as _),
+        build: build_info(),
+        started,
+        http: http.clone(),
+    };
+    let api_addr = api.addr;
+
+    let blocking = BlockingConfig {
+        mode: config.dns.blocking_mode,
+        ttl: config.dns.blocked_ttl,
+        soa_mname: config.dns.soa_mname,
+        soa_rname: config.dns.soa_rname,
+        txt_reason: config.dns.blocked_txt_reason,
+    };
+    info!(mode = ?blocking.mode, ttl = blocking.ttl, txt_reason = blocking.txt_reason, "Mode de blocage");
+
+    let protocol = config.upstream.protocol;
+    let upstreams = parse_upstreams(&config.upstream.servers.join(","), protocol.default_port())?;
+    let upstream_timeout = Duration::from_secs(config.upstream.timeout_secs);
+    // Nombre de passes sur la liste des upstreams
+    let upstream_attempts = config.upstream.retries;
+    let strategy = config.upstream.strategy;
+    let dnssec = config.upstream.dnssec;
+    info!(
+        ?upstreams,
+        ?protocol,
+        ?strategy,
+        dnssec,
+        timeout = ?upstream_timeout,
+        attempts = upstream_attempts,
+        "Upstreams"
+    );
+    let upstream = UpstreamConfig::new(
+        upstreams,
+        protocol,
+        config.upstream.tls_name,
+        upstream_timeout,
+        upstream_attempts,
+        strategy,
+        dnssec,
+    )?;
+    let conditional = ConditionalForwarders::parse(
+        &config.upstream.conditional_forward.join(","),
+        upstream_timeout,
+        upstream_attempts,
+    )?;
+    info!(suffixes = ?conditional.suffixes(), "Transferts conditionnels");
+    let ecs_passthrough = config.dns.ecs_passthrough;
+
+    let cache = CacheConfig {
+        max_entries: config.cache.size,
+        min_ttl: config.cache.min_ttl,
+        max_ttl: config.cache.max_ttl,
+        negative_ttl: config.cache.negative_ttl,
+        cache_nxdomain: config.cache.cache_nxdomain,
+        cache_nodata: config.cache.cache_nodata,
+    };
+    let cache_sweep_interval = Duration::from_secs(config.cache.sweep_secs);
+
+    let access = AccessConfig {
+        rate_limit_qps: config.dns.rate_limit,
+        allowed_clients: config.dns.allowed_clients,
+        forward_any: config.dns.forward_any,
+    };
+    info!(
+        qps = access.rate_limit_qps,
+        allowed_clients = ?access.allowed_clients,
+        forward_any = access.forward_any,
+        "Accès des clients"
+    );
+
+    let sources_refresh = Duration::from_secs(config.sources.refresh_hours.max(1) * 3600);
+
+    let retention_days = config.query_log.retention_days;
+
+    let (query_log, query_log_writer) = QueryLogger::start(config.query_log.verbosity);
+
+    let blocker = Arc::new(
+        DnsBlocker::new(
+            blocking,
+            upstream,
+            conditional,
+            ecs_passthrough,
+            query_log,
+            cache,
+            access,
+        )
+        .await?,
+    );
+
+    if let Some(path) = config.dns.hosts_file {
+        blocker.reload_hosts_file(&path).await;
+        tokio::spawn(watch_hosts_file(blocker.clone(), path));
+    }
 
-    println!("<Core> Starting servers…");
-    println!("<Core> DNS  : 127.0.0.2:53");
-    println!("<Core> HTTP : 0.0.0.0:3000");
-    println!("<Core> API  : 0.0.0.0:4000");
+    info!(dns = %dns_addr, http = %http_addr, api = %api_addr, "Démarrage des serveurs…");
+
+    tokio::spawn(refresh_sources(blocker.clone(), http, sources_refresh));
+    if retention_days > 0 {
+        info!(days = retention_days, "Rétention du journal des requêtes");
+        tokio::spawn(prune_query_log_task(Duration::from_secs(retention_days * 86_400)));
+    } else {
+        info!("Élagage du journal des requêtes désactivé");
+    }
 
     // Lancer les serveurs en parallèle dans des tâches séparées
-    let dns_handle = tokio::spawn(run_dns());
-    let api_handle = tokio::spawn(run_api());
-    let frontend_handle = tokio::spawn(serve_frontend());
+    let dns_handle = tokio::spawn(run_dns(
+        blocker.clone(),
+        dns_addr,
+        dns_fallback_port,
+        cache_sweep_interval,
+    ));
+    let api_handle = tokio::spawn(run_api(api, blocker.clone()));
+    let frontend_handle = (!unified).then(|| tokio::spawn(serve_frontend(http_addr)));
+    let frontend = async move {
+        match frontend_handle {
+            Some(handle) => {
+                let _ = handle.await;
+            }
+            // Servi par le serveur de l'API
+            None => std::future::pending().await,
+        }
+    };
 
     // Attendre qu'une des tâches se termine avec une erreur
     tokio::select! {
         result = dns_handle => {
             match result {
                 Ok(Ok(())) => {
-                    eprintln!("<Core> Le serveur DNS s'est terminé de manière inattendue");
+                    error!("Le serveur DNS s'est terminé de manière inattendue");
                     return Err(anyhow::anyhow!("Serveur DNS terminé"));
                 }
                 Ok(Err(e)) => {
-                    eprintln!("<Core> ERREUR DNS: {:#}", e);
+                    error!("ERREUR DNS: {:#}", e);
                     return Err(e);
                 }
                 Err(e) => {
-                    eprintln!("<Core> ERREUR lors de l'exécution du serveur DNS: {:#}", e);
+                    error!("ERREUR lors de l'exécution du serveur DNS: {:#}", e);
                     return Err(anyhow::anyhow!("Erreur d'exécution DNS: {}", e));
                 }
             }
@@ -101,22 +414,59 @@ async fn main() -> anyhow::Result<()> {
         result = api_handle => {
             match result {
                 Ok(Ok(())) => {
-                    eprintln!("<Core> Le serveur API s'est terminé de manière inattendue");
+                    error!("Le serveur API s'est terminé de manière inattendue");
                     return Err(anyhow::anyhow!("Serveur API terminé"));
                 }
                 Ok(Err(e)) => {
-                    eprintln!("<Core> ERREUR API: {:#}", e);
+                    error!("ERREUR API: {:#}", e);
                     return Err(e);
                 }
                 Err(e) => {
-                    eprintln!("<Core> ERREUR lors de l'exécution du serveur API: {:#}", e);
+                    error!("ERREUR lors de l'exécution du serveur API: {:#}", e);
                     return Err(anyhow::anyhow!("Erreur d'exécution API: {}", e));
                 }
             }
         }
-        _ = frontend_handle => {
-            eprintln!("<Core> Le serveur frontend s'est terminé de manière inattendue");
+        _ = frontend => {
+            error!("Le serveur frontend s'est terminé de manière inattendue");
             return Err(anyhow::anyhow!("Serveur frontend terminé"));
         }
+        _ = shutdown_signal() => {
+            info!("Arrêt demandé, fermeture des serveurs…");
+            query_log_writer.flush().await;
+            blocker.save_cache().await;
+            return Ok(());
+        }
+    }
+}
+
+// Ctrl+C, ou SIGTERM envoyé par systemd / docker
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!(error = %e, "Impossible d'écouter Ctrl+C");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                error!(error = %e, "Impossible d'écouter SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
     }
 }
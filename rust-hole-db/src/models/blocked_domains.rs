@@ -10,9 +10,25 @@ pub struct Model {
     pub id: i64,
     #[sea_orm(column_type = "Text")]
     pub domain: String,
+    pub source_id: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::blocklist_sources::Entity",
+        from = "Column::SourceId",
+        to = "super::blocklist_sources::Column::Id",
+        on_update = "NoAction",
+        on_delete = "NoAction"
+    )]
+    BlocklistSources,
+}
+
+impl Related<super::blocklist_sources::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::BlocklistSources.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
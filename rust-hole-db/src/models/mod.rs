@@ -2,4 +2,14 @@
 
 pub mod prelude;
 
+pub mod blocked_allowlist;
 pub mod blocked_domains;
+pub mod blocklist_categories;
+pub mod blocklist_sources;
+pub mod cache;
+pub mod clients;
+pub mod group_blocked_domains;
+pub mod groups;
+pub mod local_records;
+pub mod query_log;
+pub mod regex_blocklist;
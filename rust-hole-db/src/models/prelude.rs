@@ -1,3 +1,13 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 2.0
 
+pub use super::blocked_allowlist::Entity as BlockedAllowlist;
 pub use super::blocked_domains::Entity as BlockedDomains;
+pub use super::blocklist_categories::Entity as BlocklistCategories;
+pub use super::blocklist_sources::Entity as BlocklistSources;
+pub use super::cache::Entity as Cache;
+pub use super::clients::Entity as Clients;
+pub use super::group_blocked_domains::Entity as GroupBlockedDomains;
+pub use super::groups::Entity as Groups;
+pub use super::local_records::Entity as LocalRecords;
+pub use super::query_log::Entity as QueryLog;
+pub use super::regex_blocklist::Entity as RegexBlocklist;
@@ -0,0 +1,52 @@
+// Base partagée par les tests du workspace (feature `testing`)
+use std::os::raw::c_int;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::sync::OnceCell;
+
+use crate::{
+    init_db, DbConfig, DEFAULT_BUSY_TIMEOUT_SECS, DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_MAX_CONNECTIONS,
+    DEFAULT_MIN_CONNECTIONS,
+};
+
+unsafe extern "C" {
+    fn atexit(callback: extern "C" fn()) -> c_int;
+}
+
+fn db_path() -> PathBuf {
+    std::env::temp_dir().join(format!("rusthole-test-{}.db", std::process::id()))
+}
+
+// Le pool garde ses connexions jusqu'à la fin du processus : la base est
+// supprimée à la sortie, avec les fichiers du WAL
+extern "C" fn remove_db_files() {
+    let path = db_path();
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = path.clone().into_os_string();
+        file.push(suffix);
+        let _ = std::fs::remove_file(file);
+    }
+}
+
+// Base SQLite propre au binaire de test. Les tests la partagent : chacun
+// utilise ses propres domaines
+pub async fn test_db() {
+    static INIT: OnceCell<()> = OnceCell::const_new();
+    INIT.get_or_init(|| async {
+        remove_db_files();
+        // SAFETY: fonction sans état, enregistrée une seule fois
+        unsafe {
+            atexit(remove_db_files);
+        }
+        let config = DbConfig {
+            url: format!("sqlite://{}?mode=rwc", db_path().display()),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            min_connections: DEFAULT_MIN_CONNECTIONS,
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            busy_timeout: Duration::from_secs(DEFAULT_BUSY_TIMEOUT_SECS),
+        };
+        init_db(&config).await.expect("base de test");
+    })
+    .await;
+}
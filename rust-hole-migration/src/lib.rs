@@ -0,0 +1,73 @@
+pub use sea_orm_migration::prelude::*;
+
+mod m20261014_000001_create_tables;
+mod m20261014_000002_create_regex_blocklist;
+mod m20261014_000003_add_source_enabled;
+mod m20261014_000004_add_source_categories;
+mod m20261014_000005_add_source_errors;
+mod m20261015_000005_add_blocked_domain_source;
+mod m20261015_000006_blocked_domains_per_source;
+mod m20261015_000007_add_query_log_source;
+mod m20261015_000008_unique_manual_blocked_domains;
+
+pub struct Migrator;
+
+#[async_trait::async_trait]
+impl MigratorTrait for Migrator {
+    fn migrations() -> Vec<Box<dyn MigrationTrait>> {
+        vec![
+            Box::new(m20261014_000001_create_tables::Migration),
+            Box::new(m20261014_000002_create_regex_blocklist::Migration),
+            Box::new(m20261014_000003_add_source_enabled::Migration),
+            Box::new(m20261014_000004_add_source_categories::Migration),
+            Box::new(m20261014_000005_add_source_errors::Migration),
+            Box::new(m20261015_000005_add_blocked_domain_source::Migration),
+            Box::new(m20261015_000006_blocked_domains_per_source::Migration),
+            Box::new(m20261015_000007_add_query_log_source::Migration),
+            Box::new(m20261015_000008_unique_manual_blocked_domains::Migration),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm_migration::sea_orm::{ConnectionTrait, Database, Statement};
+
+    // Schéma livré avant les migrations, avec des domaines déjà bloqués
+    const BASELINE: &str = "CREATE TABLE blocked_domains (
+        id INTEGER PRIMARY KEY,
+        domain TEXT NOT NULL
+    )";
+
+    #[tokio::test]
+    async fn baseline_database_is_upgraded() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        db.execute_unprepared(BASELINE).await.unwrap();
+        // Rien n'empêchait alors les doublons
+        db.execute_unprepared(
+            "INSERT INTO blocked_domains (domain) VALUES ('ads.example.com'), ('tracker.example.com'), ('ads.example.com')",
+        )
+        .await
+        .unwrap();
+
+        Migrator::up(&db, None).await.unwrap();
+
+        let manager = SchemaManager::new(&db);
+        assert!(manager.has_column("blocked_domains", "source_id").await.unwrap());
+        assert!(manager.has_index("blocked_domains", "idx_blocked_domains_source_domain").await.unwrap());
+        // Les domaines existants restent, comme ajouts manuels et sans doublon
+        let manual = db
+            .query_one_raw(Statement::from_string(
+                db.get_database_backend(),
+                "SELECT COUNT(*) AS n FROM blocked_domains WHERE source_id IS NULL",
+            ))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(manual.try_get::<i64>("", "n").unwrap(), 2);
+
+        // Une seconde passe ne change rien
+        Migrator::up(&db, None).await.unwrap();
+    }
+}
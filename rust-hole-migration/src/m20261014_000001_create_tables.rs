@@ -0,0 +1,252 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+// Schéma initial. `if_not_exists` : les bases créées avant les migrations ont
+// déjà `blocked_domains`, sans colonne source ; `add_blocked_domain_source`
+// la leur ajoute
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(BlocklistSources::Table)
+                    .if_not_exists()
+                    .col(big_pk_auto(BlocklistSources::Id))
+                    .col(text(BlocklistSources::Url))
+                    .col(big_integer_null(BlocklistSources::LastUpdated))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(BlockedDomains::Table)
+                    .if_not_exists()
+                    .col(big_pk_auto(BlockedDomains::Id))
+                    .col(text(BlockedDomains::Domain))
+                    .col(big_integer_null(BlockedDomains::SourceId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(BlockedDomains::Table, BlockedDomains::SourceId)
+                            .to(BlocklistSources::Table, BlocklistSources::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(BlockedAllowlist::Table)
+                    .if_not_exists()
+                    .col(big_pk_auto(BlockedAllowlist::Id))
+                    .col(text(BlockedAllowlist::Domain))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(QueryLog::Table)
+                    .if_not_exists()
+                    .col(big_pk_auto(QueryLog::Id))
+                    .col(big_integer(QueryLog::Timestamp))
+                    .col(text(QueryLog::ClientIp))
+                    .col(text(QueryLog::Domain))
+                    .col(text(QueryLog::QueryType))
+                    .col(text(QueryLog::Status))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_query_log_timestamp")
+                    .table(QueryLog::Table)
+                    .col(QueryLog::Timestamp)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Groups::Table)
+                    .if_not_exists()
+                    .col(big_pk_auto(Groups::Id))
+                    .col(text(Groups::Name))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Clients::Table)
+                    .if_not_exists()
+                    .col(big_pk_auto(Clients::Id))
+                    .col(text(Clients::Ip))
+                    .col(big_integer(Clients::GroupId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Clients::Table, Clients::GroupId)
+                            .to(Groups::Table, Groups::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(GroupBlockedDomains::Table)
+                    .if_not_exists()
+                    .col(big_pk_auto(GroupBlockedDomains::Id))
+                    .col(big_integer(GroupBlockedDomains::GroupId))
+                    .col(text(GroupBlockedDomains::Domain))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(GroupBlockedDomains::Table, GroupBlockedDomains::GroupId)
+                            .to(Groups::Table, Groups::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(LocalRecords::Table)
+                    .if_not_exists()
+                    .col(big_pk_auto(LocalRecords::Id))
+                    .col(text(LocalRecords::Domain))
+                    .col(text(LocalRecords::RecordType))
+                    .col(text(LocalRecords::Value))
+                    .col(big_integer(LocalRecords::Ttl))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Cache::Table)
+                    .if_not_exists()
+                    .col(big_pk_auto(Cache::Id))
+                    .col(text(Cache::Domain))
+                    .col(big_integer(Cache::RecordType))
+                    .col(blob_null(Cache::Subnet))
+                    .col(blob(Cache::Message))
+                    .col(boolean(Cache::Negative))
+                    .col(big_integer(Cache::ExpiresAt))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Ordre inverse de la création, à cause des clés étrangères
+        for table in [
+            Cache::Table.into_iden(),
+            LocalRecords::Table.into_iden(),
+            GroupBlockedDomains::Table.into_iden(),
+            Clients::Table.into_iden(),
+            Groups::Table.into_iden(),
+            QueryLog::Table.into_iden(),
+            BlockedAllowlist::Table.into_iden(),
+            BlockedDomains::Table.into_iden(),
+            BlocklistSources::Table.into_iden(),
+        ] {
+            manager
+                .drop_table(Table::drop().table(table).to_owned())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum BlocklistSources {
+    Table,
+    Id,
+    Url,
+    LastUpdated,
+}
+
+#[derive(DeriveIden)]
+enum BlockedDomains {
+    Table,
+    Id,
+    Domain,
+    SourceId,
+}
+
+#[derive(DeriveIden)]
+enum BlockedAllowlist {
+    Table,
+    Id,
+    Domain,
+}
+
+#[derive(DeriveIden)]
+enum QueryLog {
+    Table,
+    Id,
+    Timestamp,
+    ClientIp,
+    Domain,
+    QueryType,
+    Status,
+}
+
+#[derive(DeriveIden)]
+enum Groups {
+    Table,
+    Id,
+    Name,
+}
+
+#[derive(DeriveIden)]
+enum Clients {
+    Table,
+    Id,
+    Ip,
+    GroupId,
+}
+
+#[derive(DeriveIden)]
+enum GroupBlockedDomains {
+    Table,
+    Id,
+    GroupId,
+    Domain,
+}
+
+#[derive(DeriveIden)]
+enum LocalRecords {
+    Table,
+    Id,
+    Domain,
+    RecordType,
+    Value,
+    Ttl,
+}
+
+#[derive(DeriveIden)]
+enum Cache {
+    Table,
+    Id,
+    Domain,
+    RecordType,
+    Subnet,
+    Message,
+    Negative,
+    ExpiresAt,
+}
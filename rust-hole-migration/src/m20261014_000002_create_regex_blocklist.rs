@@ -0,0 +1,33 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RegexBlocklist::Table)
+                    .if_not_exists()
+                    .col(big_pk_auto(RegexBlocklist::Id))
+                    .col(text(RegexBlocklist::Pattern))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RegexBlocklist::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RegexBlocklist {
+    Table,
+    Id,
+    Pattern,
+}
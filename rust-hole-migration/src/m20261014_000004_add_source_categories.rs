@@ -0,0 +1,57 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(BlocklistSources::Table)
+                    .add_column(text_null(BlocklistSources::Category))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(BlocklistCategories::Table)
+                    .if_not_exists()
+                    .col(text(BlocklistCategories::Name).primary_key())
+                    .col(boolean(BlocklistCategories::Enabled).default(true))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(BlocklistCategories::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(BlocklistSources::Table)
+                    .drop_column(BlocklistSources::Category)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum BlocklistSources {
+    Table,
+    Category,
+}
+
+#[derive(DeriveIden)]
+enum BlocklistCategories {
+    Table,
+    Name,
+    Enabled,
+}
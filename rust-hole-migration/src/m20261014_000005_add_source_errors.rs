@@ -0,0 +1,55 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// SQLite n'accepte qu'une colonne par ALTER TABLE
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(BlocklistSources::Table)
+                    .add_column(text_null(BlocklistSources::LastError))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(BlocklistSources::Table)
+                    .add_column(big_integer_null(BlocklistSources::LastErrorAt))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(BlocklistSources::Table)
+                    .drop_column(BlocklistSources::LastErrorAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(BlocklistSources::Table)
+                    .drop_column(BlocklistSources::LastError)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum BlocklistSources {
+    Table,
+    LastError,
+    LastErrorAt,
+}
@@ -0,0 +1,69 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::schema::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, DatabaseBackend};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Les bases antérieures aux migrations ont un `blocked_domains(id, domain)`
+// que `create_tables` ne touche pas : la colonne source y manque. Les lignes
+// existantes deviennent des ajouts manuels (source NULL)
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.has_column("blocked_domains", "source_id").await? {
+            return Ok(());
+        }
+        match manager.get_database_backend() {
+            // SQLite n'ajoute pas de clé étrangère à une table existante,
+            // sauf déclarée avec la colonne
+            DatabaseBackend::Sqlite => {
+                manager
+                    .get_connection()
+                    .execute_unprepared(
+                        "ALTER TABLE blocked_domains ADD COLUMN source_id bigint NULL REFERENCES blocklist_sources(id)",
+                    )
+                    .await?;
+                Ok(())
+            }
+            _ => {
+                manager
+                    .alter_table(
+                        Table::alter()
+                            .table(BlockedDomains::Table)
+                            .add_column(big_integer_null(BlockedDomains::SourceId))
+                            .to_owned(),
+                    )
+                    .await?;
+                manager
+                    .create_foreign_key(
+                        ForeignKey::create()
+                            .name(FK_NAME)
+                            .from(BlockedDomains::Table, BlockedDomains::SourceId)
+                            .to(BlocklistSources::Table, BlocklistSources::Id)
+                            .to_owned(),
+                    )
+                    .await
+            }
+        }
+    }
+
+    // Rien à défaire : sur une base récente la colonne vient de `create_tables`
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Ok(())
+    }
+}
+
+const FK_NAME: &str = "fk_blocked_domains_source_id";
+
+#[derive(DeriveIden)]
+enum BlockedDomains {
+    Table,
+    SourceId,
+}
+
+#[derive(DeriveIden)]
+enum BlocklistSources {
+    Table,
+    Id,
+}
@@ -0,0 +1,49 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DatabaseBackend;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const INDEX_NAME: &str = "idx_blocked_domains_source_domain";
+// MySQL n'indexe une colonne TEXT que sur un préfixe : un domaine fait au
+// plus 253 caractères
+const MYSQL_DOMAIN_PREFIX: u32 = 255;
+
+// Une ligne par (source, domaine) : un domaine listé par deux sources reste
+// bloqué tant que l'une d'elles est active. Les ajouts manuels (source NULL)
+// échappent à cet index : voir `unique_manual_blocked_domains`
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let mut index = Index::create();
+        index
+            .if_not_exists()
+            .name(INDEX_NAME)
+            .table(BlockedDomains::Table)
+            .col(BlockedDomains::SourceId)
+            .unique();
+        match manager.get_database_backend() {
+            DatabaseBackend::MySql => index.col((BlockedDomains::Domain, MYSQL_DOMAIN_PREFIX)),
+            _ => index.col(BlockedDomains::Domain),
+        };
+        manager.create_index(index.to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(INDEX_NAME)
+                    .table(BlockedDomains::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum BlockedDomains {
+    Table,
+    SourceId,
+    Domain,
+}
@@ -0,0 +1,68 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, DatabaseBackend};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const INDEX_NAME: &str = "idx_blocked_domains_manual_domain";
+
+// Un seul ajout manuel (source NULL) par domaine : l'index (source_id, domain)
+// ne compare pas les NULL. Les doublons déjà présents sont supprimés avant,
+// en gardant la première ligne
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        // Table dérivée : MySQL refuse une sous-requête sur la table modifiée
+        db.execute_unprepared(
+            "DELETE FROM blocked_domains WHERE source_id IS NULL AND id NOT IN (\
+             SELECT id FROM (SELECT MIN(id) AS id FROM blocked_domains WHERE source_id IS NULL GROUP BY domain) AS kept)",
+        )
+        .await?;
+
+        match manager.get_database_backend() {
+            // Pas d'index partiel sous MySQL : index fonctionnel, NULL pour les
+            // lignes d'une source
+            DatabaseBackend::MySql => {
+                db.execute_unprepared(&format!(
+                    "CREATE UNIQUE INDEX {INDEX_NAME} ON blocked_domains \
+                     ((CAST(IF(source_id IS NULL, domain, NULL) AS CHAR(255))))"
+                ))
+                .await?;
+                Ok(())
+            }
+            _ => {
+                manager
+                    .create_index(
+                        Index::create()
+                            .if_not_exists()
+                            .name(INDEX_NAME)
+                            .table(BlockedDomains::Table)
+                            .col(BlockedDomains::Domain)
+                            .unique()
+                            .and_where(Expr::col(BlockedDomains::SourceId).is_null())
+                            .to_owned(),
+                    )
+                    .await
+            }
+        }
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(INDEX_NAME)
+                    .table(BlockedDomains::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum BlockedDomains {
+    Table,
+    SourceId,
+    Domain,
+}